@@ -0,0 +1,80 @@
+#[macro_use]
+extern crate criterion;
+extern crate chrono;
+extern crate petronel;
+extern crate petronel_gbfrf;
+
+use chrono::Utc;
+use criterion::Criterion;
+use petronel::model::{BossImageUrl, Language, RaidBoss, RaidTweet};
+use petronel_gbfrf::protobuf::{FollowStatusResponse, ResponseMessage};
+use petronel_gbfrf::protobuf::convert;
+use petronel_gbfrf::protobuf::response_message::Data;
+use petronel_gbfrf::websocket;
+use std::collections::HashSet;
+
+fn raid_tweet() -> RaidTweet {
+    RaidTweet {
+        tweet_id: 920603283346259968,
+        boss_name: "Lv60 オオゾラッコ".to_string().into(),
+        raid_id: "ABCD1234".to_string().into(),
+        user: "walfieee".to_string().into(),
+        user_image: Some("https://pbs.twimg.com/profile_images/1/normal.png".to_string().into()),
+        text: Some("Help me please".to_string().into()),
+        created_at: Utc::now(),
+        language: Language::Japanese,
+    }
+}
+
+fn raid_boss() -> RaidBoss {
+    let mut translations = HashSet::with_capacity(1);
+    translations.insert("Lvl 60 Ozorotter".to_string().into());
+
+    RaidBoss {
+        name: "Lv60 オオゾラッコ".to_string().into(),
+        level: 60,
+        image: Some(BossImageUrl::from(
+            "https://pbs.twimg.com/media/CT6cUf4VEAAB3VW.jpg".to_string(),
+        )),
+        language: Language::Japanese,
+        translations,
+    }
+}
+
+// A response whose encoded length is at least `len` bytes
+fn response_of_len(len: usize) -> ResponseMessage {
+    ResponseMessage {
+        data: Some(Data::FollowStatusMessage(FollowStatusResponse {
+            followed_boss_names: vec!["x".repeat(len)],
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+fn to_proto(c: &mut Criterion) {
+    let tweet = raid_tweet();
+    c.bench_function("tweet_to_proto", move |b| {
+        b.iter(|| convert::tweet_to_proto(&tweet))
+    });
+
+    let boss = raid_boss();
+    c.bench_function("boss_to_proto", move |b| {
+        b.iter(|| convert::boss_to_proto(&boss))
+    });
+}
+
+// One for each way of encoding the payload length
+fn serialize_protobuf(c: &mut Criterion) {
+    let sizes = vec![("small", 64), ("medium", 8 * 1024), ("large", 256 * 1024)];
+
+    for (name, len) in sizes {
+        let message = response_of_len(len);
+        c.bench_function(&format!("serialize_protobuf {}", name), move |b| {
+            b.iter(|| websocket::serialize_protobuf(message.clone()))
+        });
+    }
+}
+
+criterion_group!(benches, to_proto, serialize_protobuf);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+#[macro_use]
+extern crate criterion;
+extern crate chrono;
+extern crate futures;
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate tokio_core;
+
+use chrono::Utc;
+use criterion::Criterion;
+use futures::{future, Stream};
+use petronel::model::{BossImageUrl, Language, RaidBoss, RaidTweet};
+use petronel_gbfrf::bench::{Encoder, Subscriber};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio_core::reactor::{Core, Remote};
+
+const SUBSCRIBERS: usize = 10_000;
+
+fn raid_tweet() -> RaidTweet {
+    RaidTweet {
+        tweet_id: 920603283346259968,
+        boss_name: "Lv60 オオゾラッコ".to_string().into(),
+        raid_id: "ABCD1234".to_string().into(),
+        user: "walfieee".to_string().into(),
+        user_image: Some("https://pbs.twimg.com/profile_images/1/normal.png".to_string().into()),
+        text: Some("Help me please".to_string().into()),
+        created_at: Utc::now(),
+        language: Language::Japanese,
+    }
+}
+
+fn raid_boss() -> RaidBoss {
+    RaidBoss {
+        name: "Lv60 オオゾラッコ".to_string().into(),
+        level: 60,
+        image: Some(BossImageUrl::from(
+            "https://pbs.twimg.com/media/CT6cUf4VEAAB3VW.jpg".to_string(),
+        )),
+        language: Language::Japanese,
+        translations: HashSet::new(),
+    }
+}
+
+// Sends one tweet to every subscriber, as the petronel worker does when a
+// tweet arrives: the filter and output limit checks, then queueing the
+// shared frame. Writing to sockets happens separately.
+fn fan_out(c: &mut Criterion) {
+    let encoder = Encoder::new(&[raid_boss()]);
+    let message = encoder.encode_tweet(&raid_tweet()).unwrap();
+
+    c.bench_function("fan out to 10k subscribers", move |b| {
+        b.iter_with_setup(
+            || {
+                (0..SUBSCRIBERS)
+                    .map(|_| Subscriber::detached())
+                    .collect::<Vec<_>>()
+            },
+            |mut subscribers| {
+                for &mut (ref mut subscriber, _) in subscribers.iter_mut() {
+                    subscriber.send(&message).unwrap();
+                }
+                subscribers
+            },
+        )
+    });
+}
+
+// Starts a reactor on its own thread, like a worker serving connections
+fn spawn_reactor() -> Remote {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        sender.send(core.remote()).unwrap();
+        core.run(future::empty::<(), ()>()).unwrap();
+    });
+    receiver.recv().unwrap()
+}
+
+// Sends one tweet to every subscriber and waits until every frame has been
+// taken off its queue, as a connection's writer does. With one reactor, the
+// writers run on the sending thread, as when petronel and the connections
+// share a core. Otherwise they're spread over that many worker reactors, and
+// every push also wakes a task on another thread.
+fn fan_out_across_reactors(c: &mut Criterion) {
+    let encoder = Encoder::new(&[raid_boss()]);
+    let message = encoder.encode_tweet(&raid_tweet()).unwrap();
+
+    c.bench_function_over_inputs(
+        "fan out to 10k subscribers, written by N reactors",
+        move |b, &reactors: &usize| {
+            // Shared by the setup and the measured routine
+            let core = RefCell::new(Core::new().unwrap());
+            let workers = if reactors > 1 {
+                (0..reactors).map(|_| spawn_reactor()).collect()
+            } else {
+                vec![]
+            };
+            let written = Arc::new(AtomicUsize::new(0));
+
+            b.iter_with_setup(
+                || {
+                    written.store(0, Ordering::SeqCst);
+                    let subscribers = (0..SUBSCRIBERS)
+                        .map(|i| {
+                            let (subscriber, frames) = Subscriber::detached();
+                            let written = written.clone();
+                            let writer = frames.for_each(move |_| {
+                                written.fetch_add(1, Ordering::SeqCst);
+                                Ok(())
+                            });
+
+                            if workers.is_empty() {
+                                core.borrow().handle().spawn(writer);
+                            } else {
+                                workers[i % workers.len()].spawn(move |_| writer);
+                            }
+                            subscriber
+                        })
+                        .collect::<Vec<_>>();
+
+                    // Let the writers on this thread start waiting for frames
+                    core.borrow_mut().turn(Some(Duration::from_millis(0)));
+                    subscribers
+                },
+                |mut subscribers| {
+                    for subscriber in subscribers.iter_mut() {
+                        subscriber.send(&message).unwrap();
+                    }
+                    while written.load(Ordering::SeqCst) < SUBSCRIBERS {
+                        core.borrow_mut().turn(Some(Duration::from_millis(0)));
+                    }
+                    subscribers
+                },
+            )
+        },
+        vec![1, 4],
+    );
+}
+
+criterion_group!(benches, fan_out, fan_out_across_reactors);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate criterion;
+extern crate petronel_gbfrf;
+extern crate tk_bufstream;
+
+use criterion::{BatchSize, Criterion};
+use petronel_gbfrf::websocket;
+use tk_bufstream::Buf;
+
+// A masked binary frame, as sent by clients
+fn masked_frame(payload_len: usize) -> Buf {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x82];
+
+    if payload_len < 126 {
+        frame.push(0x80 | payload_len as u8);
+    } else {
+        frame.extend(&[0x80 | 126, (payload_len >> 8) as u8, payload_len as u8]);
+    }
+
+    frame.extend(&mask);
+    frame.extend((0..payload_len).map(|i| i as u8 ^ mask[i % 4]));
+
+    let mut buf = Buf::new();
+    buf.extend(&frame);
+    buf
+}
+
+fn parse_frame(c: &mut Criterion) {
+    // A follow request for a few bosses, and one at the default size limit
+    let sizes = vec![("small", 100), ("max request", 4096)];
+
+    for (name, len) in sizes {
+        // Unmasking happens in place, so every iteration gets a fresh copy.
+        // The buffer is returned so that it's dropped outside the timing.
+        c.bench_function(&format!("parse_frame masked {}", name), move |b| {
+            b.iter_batched(
+                || masked_frame(len),
+                |mut buf| {
+                    criterion::black_box(websocket::parse_frame(&mut buf, 4096, true).is_ok());
+                    buf
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, parse_frame);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+// Compares replying to a tweet history request by encoding every tweet (the
+// old behavior) with reusing frames encoded when the tweets arrived
+
+#[macro_use]
+extern crate criterion;
+extern crate bytes;
+extern crate petronel_gbfrf;
+
+use bytes::Bytes;
+use criterion::Criterion;
+use petronel_gbfrf::frame_cache::FrameCache;
+use petronel_gbfrf::protobuf::{Language, RaidTweetResponse, ResponseMessage};
+use petronel_gbfrf::protobuf::response_message::Data;
+use petronel_gbfrf::websocket;
+
+// Same as the server's tweet history size
+const HISTORY_SIZE: u64 = 15;
+
+fn tweet(tweet_id: u64) -> ResponseMessage {
+    ResponseMessage {
+        data: Some(Data::RaidTweetMessage(RaidTweetResponse {
+            boss_name: "Lv60 オオゾラッコ".to_string(),
+            raid_id: format!("{:08X}", tweet_id),
+            screen_name: format!("user{}", tweet_id),
+            tweet_id: tweet_id as i64,
+            profile_image: "https://pbs.twimg.com/profile_images/1/normal.png".to_string(),
+            text: "Help me please".to_string(),
+            created_at: 1508328000000,
+            language: Language::Japanese as i32,
+        })),
+        ..Default::default()
+    }
+}
+
+fn encode_every_time(tweets: &[ResponseMessage]) -> Bytes {
+    let frames = tweets
+        .iter()
+        .filter_map(|tweet| websocket::serialize_protobuf(tweet.clone()))
+        .collect::<Vec<_>>();
+
+    let mut bytes = Bytes::with_capacity(frames.iter().map(Bytes::len).sum());
+    for frame in frames {
+        bytes.extend(frame);
+    }
+    bytes
+}
+
+fn cached_frames(cache: &mut FrameCache, tweets: &[ResponseMessage]) -> Vec<Bytes> {
+    (0..HISTORY_SIZE)
+        .filter_map(|id| {
+            cache.get_or_insert_with(id, || {
+                websocket::serialize_protobuf(tweets[id as usize].clone())
+            })
+        })
+        .collect()
+}
+
+fn tweet_history(c: &mut Criterion) {
+    let tweets = (0..HISTORY_SIZE).map(tweet).collect::<Vec<_>>();
+
+    c.bench_function("tweet history, encoded per request", move |b| {
+        b.iter(|| encode_every_time(&tweets))
+    });
+
+    let tweets = (0..HISTORY_SIZE).map(tweet).collect::<Vec<_>>();
+    let mut cache = FrameCache::new(4096);
+    cached_frames(&mut cache, &tweets);
+
+    c.bench_function("tweet history, cached frames", move |b| {
+        b.iter(|| cached_frames(&mut cache, &tweets))
+    });
+}
+
+criterion_group!(benches, tweet_history);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate petronel_gbfrf;
+extern crate tk_bufstream;
+
+use petronel_gbfrf::websocket;
+use tk_bufstream::Buf;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the mask setting and size limit, so both the
+    // client and server paths get exercised
+    let (options, data) = match data.split_first() {
+        Some((&options, data)) => (options, data),
+        None => return,
+    };
+
+    let masked = options & 1 != 0;
+    let limit = if options & 2 != 0 { 125 } else { 10 << 20 };
+
+    let mut buf = Buf::new();
+    buf.extend(data);
+
+    loop {
+        let len = buf.len();
+        let consumed = match websocket::parse_frame(&mut buf, limit, masked) {
+            Ok(Some((_frame, consumed))) => consumed,
+            _ => break,
+        };
+
+        assert!(consumed > 0 && consumed <= len);
+        buf.consume(consumed);
+    }
+});
@@ -0,0 +1,29 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate petronel_gbfrf;
+extern crate tk_bufstream;
+
+use petronel_gbfrf::websocket::{self, RequestError};
+use tk_bufstream::Buf;
+
+// Default `MAX_REQUEST_FRAME_SIZE`
+const MAX_PACKET_SIZE: usize = 4096;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = Buf::new();
+    buf.extend(data);
+
+    loop {
+        let len = buf.len();
+        let consumed = match websocket::read_request(&mut buf, MAX_PACKET_SIZE) {
+            Ok(Some((_request, consumed))) => consumed,
+            // The server skips frames that aren't valid requests and carries on
+            Err(RequestError::Decode(_, consumed)) => consumed,
+            _ => break,
+        };
+
+        assert!(consumed > 0 && consumed <= len);
+        buf.consume(consumed);
+    }
+});
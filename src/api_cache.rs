@@ -0,0 +1,110 @@
+use bytes::Bytes;
+use compression::Encoding;
+use flate2::Crc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// A serialized API response, along with its entity tag. Compressed versions
+// of the body are created the first time a client asks for them.
+pub(crate) struct CachedBody {
+    etag: String,
+    body: Bytes,
+    gzip: Mutex<Option<Bytes>>,
+    brotli: Mutex<Option<Bytes>>,
+}
+
+impl CachedBody {
+    pub(crate) fn new(body: Vec<u8>) -> Self {
+        // A CRC-32 of the content, so the tag stays the same across restarts
+        // and builds. Including the length makes collisions less likely.
+        let mut crc = Crc::new();
+        crc.update(&body);
+        let etag = format!("\"{:08x}-{:x}\"", crc.sum(), body.len());
+
+        CachedBody {
+            etag,
+            body: body.into(),
+            gzip: Mutex::new(None),
+            brotli: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.body.len()
+    }
+
+    // Each encoding of the body is a different representation, so it gets its own tag
+    pub(crate) fn etag(&self, encoding: Encoding) -> String {
+        match encoding.content_encoding() {
+            Some(suffix) => format!("{}-{}\"", self.etag.trim_right_matches('"'), suffix),
+            None => self.etag.clone(),
+        }
+    }
+
+    pub(crate) fn body(&self, encoding: Encoding) -> Bytes {
+        let compressed = match encoding {
+            Encoding::Identity => return self.body.clone(),
+            Encoding::Gzip => &self.gzip,
+            Encoding::Brotli => &self.brotli,
+        };
+
+        let mut compressed = compressed.lock().unwrap();
+        if compressed.is_none() {
+            *compressed = Some(encoding.compress(&self.body).into());
+        }
+
+        compressed.as_ref().unwrap().clone()
+    }
+
+    // Checks an `If-None-Match` header value against this body's tag
+    pub(crate) fn matches(&self, if_none_match: &str, encoding: Encoding) -> bool {
+        let etag = self.etag(encoding);
+
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_left_matches("W/") == etag)
+    }
+}
+
+// Holds the most recently serialized response, so that polling clients don't
+// cause it to be reserialized on every request
+#[derive(Clone)]
+pub(crate) struct ResponseCache {
+    pub(crate) max_age_seconds: u64,
+    current: Arc<Mutex<Option<(Instant, Arc<CachedBody>)>>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(max_age_seconds: u64) -> Self {
+        ResponseCache {
+            max_age_seconds,
+            current: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<Arc<CachedBody>> {
+        let max_age = Duration::from_secs(self.max_age_seconds);
+
+        match *self.current.lock().unwrap() {
+            Some((created_at, ref cached)) if created_at.elapsed() < max_age => {
+                Some(cached.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn update(&self, body: Vec<u8>) -> Arc<CachedBody> {
+        let cached = CachedBody::new(body);
+        let mut current = self.current.lock().unwrap();
+
+        // Keep the existing entry if the content hasn't changed
+        let cached = match *current {
+            Some((_, ref existing)) if existing.body == cached.body => existing.clone(),
+            _ => Arc::new(cached),
+        };
+
+        *current = Some((Instant::now(), cached.clone()));
+        cached
+    }
+}
@@ -0,0 +1,187 @@
+use api_cache::CachedBody;
+use petronel::error::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+
+const INDEX_FILE: &str = "index.html";
+
+pub(crate) struct StaticFile {
+    pub(crate) content_type: &'static str,
+    pub(crate) cache_control: String,
+    // Images and fonts are already compressed
+    pub(crate) compressible: bool,
+    pub(crate) body: CachedBody,
+}
+
+// Frontend files, loaded into memory at startup. Requests can only ever be
+// served from this map, so paths like `/../../etc/passwd` have nothing to match.
+pub(crate) struct StaticAssets {
+    files: HashMap<String, Arc<StaticFile>>,
+}
+
+impl StaticAssets {
+    pub(crate) fn load(dir: &Path, max_age_seconds: u64) -> Result<Self> {
+        let root = dir.canonicalize()
+            .chain_err(|| format!("failed to open static directory {}", dir.display()))?;
+
+        let mut paths = Vec::new();
+        find_files(&root, &root, &mut paths)?;
+
+        let mut files = HashMap::with_capacity(paths.len());
+        for (url_path, path) in paths {
+            let mut body = Vec::new();
+            fs::File::open(&path)
+                .and_then(|mut f| f.read_to_end(&mut body))
+                .chain_err(|| format!("failed to read {}", path.display()))?;
+
+            let (content_type, compressible) = content_type(&path);
+            let cache_control = if url_path.ends_with(INDEX_FILE) {
+                // Always revalidate, so that new deployments are picked up
+                "no-cache".to_string()
+            } else {
+                format!("public, max-age={}", max_age_seconds)
+            };
+
+            let file = StaticFile {
+                content_type,
+                cache_control,
+                compressible,
+                body: CachedBody::new(body),
+            };
+
+            files.insert(url_path, Arc::new(file));
+        }
+
+        println!("Loaded {} static files from {}", files.len(), root.display());
+
+        Ok(StaticAssets { files })
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Option<Arc<StaticFile>> {
+        let path = path.split(|c| c == '?' || c == '#').next().unwrap_or("");
+        let path = match percent_decode(path) {
+            Some(path) => path,
+            None => return None,
+        };
+        let path = path.as_str();
+
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+
+        let file = if path.ends_with('/') {
+            self.files.get(&format!("{}{}", path, INDEX_FILE))
+        } else {
+            self.files.get(path)
+        };
+
+        let last_segment = path.rsplit('/').next().unwrap_or("");
+
+        match file {
+            Some(file) => Some(file.clone()),
+            // Paths without a file extension are client-side routes of the
+            // single-page app, so they get the main page. Unknown API routes
+            // should still be a 404, though.
+            None if !last_segment.contains('.') && !path.starts_with("/api/") => {
+                self.files.get(&format!("/{}", INDEX_FILE)).cloned()
+            }
+            None => None,
+        }
+    }
+}
+
+// Decodes `%XX` escapes in a URL path, failing on malformed escapes or
+// invalid UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = match bytes.get(i + 1..i + 3) {
+                Some(hex) => hex,
+                None => return None,
+            };
+            let byte = str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            match byte {
+                Some(byte) => decoded.push(byte),
+                None => return None,
+            }
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// Collects regular files under `dir`, keyed by their URL path
+fn find_files(root: &Path, dir: &Path, output: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let entries = fs::read_dir(dir).chain_err(|| format!("failed to read {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = entry.chain_err(|| format!("failed to read {}", dir.display()))?;
+        let path = entry
+            .path()
+            .canonicalize()
+            .chain_err(|| format!("failed to resolve {}", entry.path().display()))?;
+
+        // Don't follow symlinks out of the static directory
+        if !path.starts_with(root) {
+            continue;
+        }
+
+        if path.is_dir() {
+            find_files(root, &path, output)?;
+        } else if path.is_file() {
+            let relative = path.strip_prefix(root).unwrap();
+            let url_path = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            output.push((format!("/{}", url_path), path.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+fn content_type(path: &Path) -> (&'static str, bool) {
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_ref() {
+        "html" | "htm" => ("text/html; charset=utf-8", true),
+        "js" => ("application/javascript; charset=utf-8", true),
+        "css" => ("text/css; charset=utf-8", true),
+        "json" | "map" => ("application/json", true),
+        "svg" => ("image/svg+xml", true),
+        "txt" => ("text/plain; charset=utf-8", true),
+        "xml" => ("application/xml", true),
+        "webmanifest" => ("application/manifest+json", true),
+        "ico" => ("image/x-icon", true),
+        "png" => ("image/png", false),
+        "jpg" | "jpeg" => ("image/jpeg", false),
+        "gif" => ("image/gif", false),
+        "webp" => ("image/webp", false),
+        "woff" => ("font/woff", false),
+        "woff2" => ("font/woff2", false),
+        "mp3" => ("audio/mpeg", false),
+        "ogg" => ("audio/ogg", false),
+        _ => ("application/octet-stream", false),
+    }
+}
@@ -0,0 +1,58 @@
+// Entry points for the benchmarks into code that is otherwise private, so
+// that they measure the same paths the server runs
+
+use bytes::Bytes;
+use codec::WebsocketSubscriber;
+use config;
+use directory::BossDirectory;
+use futures::sync::mpsc;
+use limits::OutputLimits;
+use metrics::ServerMetrics;
+use outbox::Outbox;
+use petronel;
+use petronel::model::{Message as PetronelMessage, RaidBoss, RaidTweet};
+use protobuf::convert::{EncodedMessage, MessageEncoder};
+use std::sync::Arc;
+
+pub struct Message(EncodedMessage);
+
+// The encoder petronel runs every message through, with a directory of the
+// given bosses so that tweets get their boss's level
+pub struct Encoder(MessageEncoder);
+
+impl Encoder {
+    pub fn new(bosses: &[RaidBoss]) -> Self {
+        let directory = BossDirectory::new(&[]);
+        for boss in bosses {
+            directory.update(boss);
+        }
+
+        Encoder(MessageEncoder::new(
+            ::TWEET_FRAME_CACHE_SIZE,
+            directory,
+            config::DEFAULT_BOSS_LIST_CHUNK_BYTES,
+        ))
+    }
+
+    pub fn encode_tweet(&self, tweet: &RaidTweet) -> Option<Message> {
+        self.0.encode(PetronelMessage::Tweet(tweet)).map(Message)
+    }
+}
+
+// A connection's subscriber with the default output limits, whose frames go
+// to the returned stream instead of a socket
+pub struct Subscriber(WebsocketSubscriber);
+
+impl Subscriber {
+    pub fn detached() -> (Self, mpsc::UnboundedReceiver<(Bytes, usize)>) {
+        let (outbox, frames) = Outbox::detached();
+        let metrics = Arc::new(ServerMetrics::default());
+        let (subscriber, _) = WebsocketSubscriber::new(outbox, OutputLimits::default(), metrics);
+
+        (Subscriber(subscriber), frames)
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), ()> {
+        petronel::Subscriber::send(&mut self.0, &message.0)
+    }
+}
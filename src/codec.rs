@@ -1,26 +1,52 @@
+use api_cache::{CachedBody, ResponseCache};
+use assets::{StaticAssets, StaticFile};
 use bytes::Bytes;
-use futures::{future, Async, Future};
+use chrono::Utc;
+use compression::Encoding;
+use config::Config;
+use cors::CorsHeaders;
+use dedupe::RaidDeduper;
+use directory::BossDirectory;
+use filter::FollowFilter;
+use futures::{future, Async, Future, Stream};
+use futures::sync::mpsc;
+use limits::{ConnectionGuard, ConnectionLimiter, OutputLimits, SlowConsumerPolicy, TokenBucket};
+use metrics::ServerMetrics;
+use origin;
+use outbox::Outbox;
 use petronel;
-use petronel::model::BossName;
-use prost::Message;
+use petronel::model::{BossName, RaidBoss};
 use protobuf;
+use protobuf::convert::{self, EncodedMessage, MessageKind};
 use serde_json;
-use std::rc::Rc;
-use std::sync::Mutex;
+use std::collections::HashSet;
+use std::iter;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
 use tk_bufstream::{ReadBuf, WriteBuf};
 use tk_http::Status;
 use tk_http::server::{Codec, Dispatcher, Encoder, EncoderDone, Error as TkError, Head, RecvMode,
                       WebsocketHandshake};
-use tokio_core::reactor::Handle;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Interval};
 use tokio_io::{AsyncRead, AsyncWrite};
-use websocket::{self, Frame};
+use websocket::{self, ErrorEnum, Incoming, RequestError};
 
 const MAX_REQUEST_LENGTH: usize = 128_000; // Not expecting huge requests here
-const MAX_PACKET_SIZE: usize = 10 << 20;
+// How many unknown boss names in a follow request get suggestions
+const MAX_SUGGESTED_NAMES: usize = 10;
 
 pub(crate) struct RequestDispatcher<S> {
-    pub(crate) petronel_client: petronel::Client<WebsocketSubscriber<S>, Vec<u8>>,
+    pub(crate) petronel_client: petronel::Client<WebsocketSubscriber, Vec<u8>>,
     pub(crate) handle: Handle,
+    pub(crate) config: Arc<Config>,
+    pub(crate) metrics: Arc<ServerMetrics>,
+    pub(crate) connection_limiter: ConnectionLimiter,
+    pub(crate) bosses_cache: ResponseCache,
+    pub(crate) assets: Option<Arc<StaticAssets>>,
+    pub(crate) directory: BossDirectory,
+    // Address of the client, or of the proxy if the client is behind one
+    pub(crate) peer_addr: SocketAddr,
 }
 
 impl<S> Dispatcher<S> for RequestDispatcher<S>
@@ -30,22 +56,94 @@ where
     type Codec = RequestCodec<S>;
 
     fn headers_received(&mut self, headers: &Head) -> Result<Self::Codec, TkError> {
-        let websocket_handshake = headers.get_websocket_upgrade().unwrap_or(None);
+        let mut websocket_handshake = headers.get_websocket_upgrade().unwrap_or(None);
+        let client_ip = self.config.proxy.client_ip(headers, self.peer_addr.ip());
+
+        let forbidden = websocket_handshake.is_some() && match self.config.allowed_origins {
+            Some(ref allowed_origins) => match origin::origin_header(headers) {
+                Some(ref origin) => !allowed_origins.allows(origin),
+                _ => false,
+            },
+            None => false,
+        };
+
+        if forbidden {
+            ServerMetrics::incr(&self.metrics.origins_rejected);
+            websocket_handshake = None;
+        }
+
+        let path = headers.path().unwrap().to_string();
+        let is_api = path.starts_with("/api/");
+        let is_admin_path = path.starts_with("/api/admin/");
+        // Admin endpoints are never meant to be called from a browser
+        let cors = match self.config.cors {
+            Some(ref cors) if is_api && !is_admin_path => Some(cors.for_request(headers)),
+            _ => None,
+        };
+        let is_admin = is_admin_path && match self.config.admin_token {
+            Some(ref token) => header_value(headers, "Authorization")
+                .map_or(false, |value| is_bearer_token(&value, token)),
+            None => false,
+        };
 
         Ok(RequestCodec {
             petronel_client: self.petronel_client.clone(),
-            path: headers.path().unwrap().to_string(),
+            is_preflight: is_api && headers.method() == "OPTIONS",
+            is_get_or_head: headers.method() == "GET" || headers.method() == "HEAD",
+            path,
+            cors,
+            if_none_match: header_value(headers, "If-None-Match"),
+            encoding: Encoding::negotiate(
+                header_value(headers, "Accept-Encoding")
+                    .as_ref()
+                    .map(String::as_str),
+            ),
             websocket_handshake,
+            forbidden,
+            is_admin,
             handle: self.handle.clone(),
+            config: self.config.clone(),
+            metrics: self.metrics.clone(),
+            connection_limiter: self.connection_limiter.clone(),
+            bosses_cache: self.bosses_cache.clone(),
+            assets: self.assets.clone(),
+            directory: self.directory.clone(),
+            client_ip,
         })
     }
 }
 
 pub(crate) struct RequestCodec<S> {
-    petronel_client: petronel::Client<WebsocketSubscriber<S>, Vec<u8>>,
+    petronel_client: petronel::Client<WebsocketSubscriber, Vec<u8>>,
     path: String,
+    is_preflight: bool,
+    // Static files can't be posted to, etc.
+    is_get_or_head: bool,
+    cors: Option<CorsHeaders>,
+    if_none_match: Option<String>,
+    encoding: Encoding,
     websocket_handshake: Option<WebsocketHandshake>,
+    forbidden: bool,
+    // Whether the request carries the admin token
+    is_admin: bool,
     handle: Handle,
+    config: Arc<Config>,
+    metrics: Arc<ServerMetrics>,
+    connection_limiter: ConnectionLimiter,
+    bosses_cache: ResponseCache,
+    assets: Option<Arc<StaticAssets>>,
+    directory: BossDirectory,
+    client_ip: IpAddr,
+}
+
+impl<S> RequestCodec<S> {
+    fn static_file(&self) -> Option<Arc<StaticFile>> {
+        if !self.is_get_or_head {
+            return None;
+        }
+
+        self.assets.as_ref().and_then(|assets| assets.get(&self.path))
+    }
 }
 
 impl<S> Codec<S> for RequestCodec<S>
@@ -68,45 +166,124 @@ where
     }
 
     fn start_response(&mut self, mut e: Encoder<S>) -> Self::ResponseFuture {
-        if let Some(ref ws) = self.websocket_handshake {
+        if self.forbidden {
+            let body = "Forbidden";
+
+            e.status(Status::Forbidden);
+            e.add_header("Content-Type", "text/plain").unwrap();
+            e.add_length(body.len() as u64).unwrap();
+
+            if e.done_headers().unwrap() {
+                e.write_body(body.as_bytes());
+            }
+
+            Box::new(future::ok(e.done())) as Self::ResponseFuture
+        } else if let Some(ref ws) = self.websocket_handshake {
             e.status(Status::SwitchingProtocol);
             e.add_header("Connection", "upgrade").unwrap();
             e.add_header("Upgrade", "websocket").unwrap();
             e.format_header("Sec-Websocket-Accept", &ws.accept).unwrap();
             e.format_header("Sec-Websocket-Protocol", "binary").unwrap();
             e.done_headers().unwrap();
+            Box::new(future::ok(e.done())) as Self::ResponseFuture
+        } else if self.is_preflight {
+            e.status(Status::Ok);
+            if let Some(ref cors) = self.cors {
+                cors.add_preflight_to(&mut e);
+            }
+            e.add_length(0).unwrap();
+            e.done_headers().unwrap();
+
             Box::new(future::ok(e.done())) as Self::ResponseFuture
         } else if self.path == "/api/metrics.json" {
+            let server_metrics = self.metrics.clone();
+            let cors = self.cors.clone();
+            let encoding = self.encoding;
             let resp = self.petronel_client
                 .export_metrics()
-                .map(|metrics| {
-                    e.status(Status::Ok);
-                    e.add_length(metrics.len() as u64).unwrap();
-                    e.add_header("Content-Type", "application/json").unwrap();
-                    if e.done_headers().unwrap() {
-                        e.write_body(metrics.as_ref());
-                    }
-                    e.done()
+                .map(move |metrics| {
+                    let metrics = server_metrics.merge_into(&metrics);
+                    write_json(e, &metrics, encoding, cors.as_ref())
                 })
                 .map_err(|_| TkError::custom("closed by sender"));
 
             Box::new(resp) as Self::ResponseFuture
         } else if self.path == "/api/bosses.json" {
+            let cors = self.cors.clone();
+            let if_none_match = self.if_none_match.clone();
+            let encoding = self.encoding;
+            let cache_control = format!("public, max-age={}", self.bosses_cache.max_age_seconds);
+
+            if let Some(cached) = self.bosses_cache.get() {
+                let done = write_cached(
+                    e,
+                    &cached,
+                    "application/json",
+                    &cache_control,
+                    encoding,
+                    if_none_match.as_ref().map(String::as_str),
+                    cors.as_ref(),
+                );
+
+                Box::new(future::ok(done)) as Self::ResponseFuture
+            } else {
+                let cache = self.bosses_cache.clone();
+                let resp = self.petronel_client
+                    .bosses()
+                    .map(move |boss_list| {
+                        let cached = cache.update(serde_json::to_vec(&boss_list).unwrap());
+                        write_cached(
+                            e,
+                            &cached,
+                            "application/json",
+                            &cache_control,
+                            encoding,
+                            if_none_match.as_ref().map(String::as_str),
+                            cors.as_ref(),
+                        )
+                    })
+                    .map_err(|_| TkError::custom("closed by sender"));
+
+                Box::new(resp) as Self::ResponseFuture
+            }
+        } else if self.path == "/api/admin/expiring_bosses.json" && self.is_admin {
+            // Dry run of the next cache flush, listing bosses that would be removed
+            let config = self.config.clone();
+            let cors = self.cors.clone();
+            let encoding = self.encoding;
             let resp = self.petronel_client
-                .bosses()
-                .map(|boss_list| {
-                    let body = serde_json::to_vec(&boss_list).unwrap();
-                    e.status(Status::Ok);
-                    e.add_length(body.len() as u64).unwrap();
-                    e.add_header("Content-Type", "application/json").unwrap();
-                    if e.done_headers().unwrap() {
-                        e.write_body(body.as_ref());
-                    }
-                    e.done()
+                .export_metadata()
+                .map(move |bosses| {
+                    let now = Utc::now();
+                    let expiring = bosses
+                        .iter()
+                        .filter(|meta| config.expiry.is_expired(meta, now))
+                        .collect::<Vec<_>>();
+
+                    let body = serde_json::to_vec(&expiring).unwrap();
+                    write_json(e, &body, encoding, cors.as_ref())
                 })
                 .map_err(|_| TkError::custom("closed by sender"));
 
             Box::new(resp) as Self::ResponseFuture
+        } else if let Some(file) = self.static_file() {
+            let encoding = if file.compressible {
+                self.encoding
+            } else {
+                Encoding::Identity
+            };
+
+            let done = write_cached(
+                e,
+                &file.body,
+                file.content_type,
+                &file.cache_control,
+                encoding,
+                self.if_none_match.as_ref().map(String::as_str),
+                None,
+            );
+
+            Box::new(future::ok(done)) as Self::ResponseFuture
         } else {
             let body = "Not found";
 
@@ -122,116 +299,670 @@ where
         }
     }
 
-    fn hijack(&mut self, mut write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+    fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+        // The writer finishes once the outbox is dropped and everything queued
+        // has been written, so rejections still get their close frame out
+        let (outbox, outbox_writer) = Outbox::new(write_buf);
+        self.handle.spawn(outbox_writer);
+
+        let connection_guard = match self.connection_limiter.acquire(self.client_ip) {
+            Some(guard) => guard,
+            None => {
+                ServerMetrics::incr(&self.metrics.connections_rejected);
+                outbox.close(1008, b"too many connections");
+                return;
+            }
+        };
+
+        let (subscriber, new_translations) =
+            WebsocketSubscriber::new(outbox, self.config.limits.output, self.metrics.clone());
+
         // Send a Ping frame to start the connection
-        write_buf.out_buf.extend(websocket::EMPTY_PING);
-        let _ = write_buf.flush();
+        let _ = subscriber.ping();
+
+        let rate_limiter = self.config.limits.token_bucket();
+        let max_frame_size = self.config.limits.max_request_frame_size;
+        let ping_timeout = self.config.ping_timeout;
+        let directory = self.directory.clone();
+        let boss_list_chunk_size = self.config.boss_list_chunk_size;
+        let reject_unknown_follows = self.config.reject_unknown_follows;
+        let ping_interval = match Interval::new(self.config.ping_interval, &self.handle) {
+            Ok(interval) => interval,
+            Err(_) => {
+                ServerMetrics::incr(&self.metrics.connection_errors);
+                subscriber.close(1011, b"internal error");
+                return;
+            }
+        };
+        let metrics = self.metrics.clone();
 
         let subscription_future = self.petronel_client
-            .subscribe(WebsocketSubscriber {
-                write_buf: Rc::new(Mutex::new(write_buf)),
-            })
+            .subscribe(subscriber.clone())
             .map_err(|_| ())
-            .and_then(|subscription| WebsocketReader {
+            .and_then(move |subscription| WebsocketReader {
                 read_buf,
                 subscription,
+                subscriber,
+                rate_limiter,
+                max_frame_size,
+                ping_interval,
+                ping_timeout,
+                directory,
+                boss_list_chunk_size,
+                reject_unknown_follows,
+                new_translations,
+                last_activity: Instant::now(),
+                metrics,
+                _connection_guard: connection_guard,
             });
 
         self.handle.spawn(subscription_future);
     }
 }
 
-pub(crate) struct WebsocketSubscriber<S> {
-    // TODO: Better way to do this that doesn't involve Rc<Mutex<...>>
-    write_buf: Rc<Mutex<WriteBuf<S>>>,
+fn write_json<S>(
+    mut e: Encoder<S>,
+    body: &[u8],
+    encoding: Encoding,
+    cors: Option<&CorsHeaders>,
+) -> EncoderDone<S> {
+    let encoding = encoding.for_len(body.len());
+
+    let compressed;
+    let body = match encoding {
+        Encoding::Identity => body,
+        _ => {
+            compressed = encoding.compress(body);
+            &compressed
+        }
+    };
+
+    e.status(Status::Ok);
+    e.add_length(body.len() as u64).unwrap();
+    e.add_header("Content-Type", "application/json").unwrap();
+    add_encoding_headers(&mut e, encoding);
+    if let Some(cors) = cors {
+        cors.add_to(&mut e);
+    }
+    if e.done_headers().unwrap() {
+        e.write_body(body);
+    }
+    e.done()
 }
 
-impl<S> Clone for WebsocketSubscriber<S> {
-    fn clone(&self) -> Self {
-        WebsocketSubscriber {
-            write_buf: self.write_buf.clone(),
+// Responds with `304 Not Modified` if the client already has the latest version
+fn write_cached<S>(
+    mut e: Encoder<S>,
+    cached: &CachedBody,
+    content_type: &str,
+    cache_control: &str,
+    encoding: Encoding,
+    if_none_match: Option<&str>,
+    cors: Option<&CorsHeaders>,
+) -> EncoderDone<S> {
+    let encoding = encoding.for_len(cached.len());
+    let not_modified = if_none_match.map_or(false, |tags| cached.matches(tags, encoding));
+    // Checked first, so that revalidating doesn't compress the body
+    let body = if not_modified {
+        None
+    } else {
+        Some(cached.body(encoding))
+    };
+
+    match body {
+        Some(ref body) => {
+            e.status(Status::Ok);
+            e.add_length(body.len() as u64).unwrap();
+            e.add_header("Content-Type", content_type).unwrap();
         }
+        None => e.status(Status::NotModified),
     }
+    add_encoding_headers(&mut e, encoding);
+    e.add_header("ETag", &cached.etag(encoding)).unwrap();
+    e.add_header("Cache-Control", cache_control).unwrap();
+    if let Some(cors) = cors {
+        cors.add_to(&mut e);
+    }
+    if e.done_headers().unwrap() {
+        if let Some(body) = body {
+            e.write_body(&body);
+        }
+    }
+    e.done()
 }
 
-impl<S> petronel::Subscriber for WebsocketSubscriber<S>
-where
-    S: AsyncWrite,
-{
-    type Item = Bytes;
+fn add_encoding_headers<S>(e: &mut Encoder<S>, encoding: Encoding) {
+    if let Some(content_encoding) = encoding.content_encoding() {
+        e.add_header("Content-Encoding", content_encoding).unwrap();
+    }
+    e.add_header("Vary", "Accept-Encoding").unwrap();
+}
+
+// Compares in constant time, so the token can't be guessed byte by byte
+fn is_bearer_token(authorization: &str, token: &str) -> bool {
+    let mut parts = authorization.trim().splitn(2, ' ');
+    let given = match (parts.next(), parts.next()) {
+        (Some(scheme), Some(given)) if scheme.eq_ignore_ascii_case("Bearer") => given.trim(),
+        _ => return false,
+    };
+
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn header_value(headers: &Head, name: &str) -> Option<String> {
+    headers
+        .headers()
+        .find(|&(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| ::std::str::from_utf8(value).ok())
+        .map(String::from)
+}
+
+// Bosses a connection follows. Translations followed along with a boss are
+// implied, so unfollowing the boss leaves them followed if the client also
+// followed them itself. They can't be unfollowed on their own.
+#[derive(Default)]
+struct Follows {
+    explicit: HashSet<BossName>,
+    with_translations: HashSet<BossName>,
+    implied: HashSet<BossName>,
+}
+
+#[derive(Clone)]
+pub(crate) struct WebsocketSubscriber {
+    outbox: Outbox,
+    limits: OutputLimits,
+    metrics: Arc<ServerMetrics>,
+    // Shared with petronel's copy, so that follow requests can change it
+    filter: Arc<RwLock<FollowFilter>>,
+    follows: Arc<Mutex<Follows>>,
+    // Translations learned after following, for the reader to follow
+    new_translations: mpsc::UnboundedSender<BossName>,
+    // Off unless the client asks for it
+    dedupe: Arc<Mutex<Option<RaidDeduper>>>,
+}
+
+impl WebsocketSubscriber {
+    // Returns the subscriber, and a stream of translations it learns of that
+    // the connection's reader should follow
+    pub(crate) fn new(
+        outbox: Outbox,
+        limits: OutputLimits,
+        metrics: Arc<ServerMetrics>,
+    ) -> (Self, mpsc::UnboundedReceiver<BossName>) {
+        let (translations_sender, new_translations) = mpsc::unbounded();
+        let subscriber = WebsocketSubscriber {
+            outbox,
+            limits,
+            metrics,
+            filter: Arc::new(RwLock::new(FollowFilter::default())),
+            follows: Arc::new(Mutex::new(Follows::default())),
+            new_translations: translations_sender,
+            dedupe: Arc::new(Mutex::new(None)),
+        };
+
+        (subscriber, new_translations)
+    }
+
+    fn close(&self, code: u16, reason: &[u8]) {
+        self.outbox.close(code, reason);
+    }
+
+    fn ping(&self) -> Result<(), ()> {
+        self.outbox.push(Bytes::from_static(websocket::EMPTY_PING), 1)
+    }
+
+    fn pong(&self, payload: &[u8]) -> Result<(), ()> {
+        self.outbox.push(websocket::pong_frame(payload), 1)
+    }
+
+    fn set_filter(&self, filter: FollowFilter) {
+        *self.filter.write().unwrap() = filter;
+    }
+
+    fn set_dedupe_window(&self, seconds: u64) {
+        let dedupe = if seconds > 0 {
+            Some(RaidDeduper::new(Duration::from_secs(seconds)))
+        } else {
+            None
+        };
+
+        *self.dedupe.lock().unwrap() = dedupe;
+    }
+
+    // Returns the translations to follow along with the boss, if asked for
+    fn follow(
+        &self,
+        name: &BossName,
+        with_translations: bool,
+        directory: &BossDirectory,
+    ) -> Vec<BossName> {
+        let mut guard = self.follows.lock().unwrap();
+        let follows = &mut *guard;
+        follows.explicit.insert(name.clone());
+
+        if !with_translations {
+            return vec![];
+        }
+
+        let translations = translations_of(name, directory);
+        follows.with_translations.insert(name.clone());
+        follows.implied.extend(translations.iter().cloned());
+
+        translations
+    }
+
+    // Whether the boss is followed only because it translates a boss that was
+    // followed with its translations, which can't be unfollowed separately
+    fn is_followed_as_translation(&self, name: &BossName) -> bool {
+        let follows = self.follows.lock().unwrap();
+        follows.implied.contains(name) && !follows.explicit.contains(name)
+    }
+
+    // Returns the boss and the translations followed along with it, except
+    // those that are still followed for another reason
+    fn unfollow(&self, name: &BossName, directory: &BossDirectory) -> Vec<BossName> {
+        let mut guard = self.follows.lock().unwrap();
+        let follows = &mut *guard;
+        follows.explicit.remove(name);
+
+        let mut unfollowed = vec![name.clone()];
+        if follows.with_translations.remove(name) {
+            unfollowed.extend(translations_of(name, directory));
+
+            follows.implied = follows
+                .with_translations
+                .iter()
+                .flat_map(|boss| translations_of(boss, directory))
+                .collect();
+        }
+
+        unfollowed
+            .into_iter()
+            .filter(|boss| !follows.explicit.contains(boss) && !follows.implied.contains(boss))
+            .collect()
+    }
+
+    fn handle_boss_update(&self, boss: &RaidBoss) {
+        let mut guard = self.follows.lock().unwrap();
+        let follows = &mut *guard;
+        let is_followed = follows.with_translations.contains(&boss.name)
+            || boss.translations
+                .iter()
+                .any(|name| follows.with_translations.contains(name));
+
+        if !is_followed {
+            return;
+        }
+
+        for name in iter::once(&boss.name).chain(boss.translations.iter()) {
+            if follows.with_translations.contains(name) {
+                continue;
+            }
+
+            if follows.implied.insert(name.clone()) && !follows.explicit.contains(name) {
+                let _ = self.new_translations.unbounded_send(name.clone());
+            }
+        }
+    }
+}
+
+fn translations_of(name: &BossName, directory: &BossDirectory) -> Vec<BossName> {
+    directory
+        .get(name)
+        .map_or(vec![], |boss| boss.translations.iter().cloned().collect())
+}
+
+impl petronel::Subscriber for WebsocketSubscriber {
+    type Item = EncodedMessage;
 
     fn send(&mut self, message: &Self::Item) -> Result<(), ()> {
-        // TODO: Better way of doing this that doesn't require Mutex
-        let mut write_buf = self.write_buf.lock().unwrap();
-        write_buf.out_buf.extend(message);
-        write_buf.flush().map_err(|_| ())
+        if self.outbox.is_closed() {
+            return Err(());
+        }
+
+        if let Some(ref boss) = message.updated_boss {
+            self.handle_boss_update(boss);
+        }
+
+        if let Some(ref key) = message.filter_key {
+            if !self.filter.read().unwrap().allows(key) {
+                return Ok(());
+            }
+        }
+
+        if let Some(ref raid) = message.raid {
+            let duplicates = match *self.dedupe.lock().unwrap() {
+                Some(ref mut dedupe) => dedupe.record_duplicate(&raid.raid_id, Instant::now()),
+                None => None,
+            };
+
+            if let Some(count) = duplicates {
+                return match convert::raid_duplicate_message(raid, count) {
+                    Some(duplicate) => self.send(&duplicate),
+                    None => Ok(()),
+                };
+            }
+        }
+
+        let (queued_bytes, queued_frames) = self.outbox.queued();
+        let is_exceeded = queued_frames > 0
+            && self.limits.is_exceeded(
+                queued_bytes + message.len(),
+                queued_frames + message.frames.len(),
+            );
+
+        if is_exceeded {
+            // Boss updates are small and infrequent, so they're still sent
+            // when dropping tweets, up to twice the byte limit
+            let is_droppable = self.limits.policy == SlowConsumerPolicy::Drop
+                && queued_bytes <= self.limits.max_bytes * 2;
+
+            if !is_droppable {
+                ServerMetrics::incr(&self.metrics.slow_consumers_disconnected);
+                self.close(1008, b"slow consumer");
+                return Err(());
+            } else if message.kind == MessageKind::Tweet {
+                ServerMetrics::incr(&self.metrics.tweets_dropped);
+                return Ok(());
+            }
+        }
+
+        // Only the reference counts are touched, not the frame data
+        for frame in message.frames.iter() {
+            self.outbox.push(frame.clone(), 1)?;
+        }
+
+        // Only now, so that if the first tweet for a raid is dropped, the
+        // next copy is sent in full rather than as a duplicate
+        if let Some(ref raid) = message.raid {
+            if let Some(ref mut dedupe) = *self.dedupe.lock().unwrap() {
+                dedupe.record_sent(&raid.raid_id, Instant::now());
+            }
+        }
+
+        Ok(())
     }
 }
 
 pub struct WebsocketReader<S> {
     read_buf: ReadBuf<S>,
-    subscription: petronel::Subscription<WebsocketSubscriber<S>, Vec<u8>>,
+    subscription: petronel::Subscription<WebsocketSubscriber, Vec<u8>>,
+    subscriber: WebsocketSubscriber,
+    rate_limiter: Option<TokenBucket>,
+    max_frame_size: usize,
+    ping_interval: Interval,
+    ping_timeout: Duration,
+    directory: BossDirectory,
+    boss_list_chunk_size: usize,
+    reject_unknown_follows: bool,
+    new_translations: mpsc::UnboundedReceiver<BossName>,
+    // When anything was last received from the client
+    last_activity: Instant,
+    metrics: Arc<ServerMetrics>,
+    _connection_guard: ConnectionGuard,
 }
 
 impl<S> WebsocketReader<S> {
-    fn handle_message(
-        subscription: &mut petronel::Subscription<WebsocketSubscriber<S>, Vec<u8>>,
-        message: &protobuf::RequestMessage,
-    ) {
+    // Handles a request, then sends its reply. Requests without one are
+    // acknowledged if the client gave them an ID.
+    fn handle_request(&mut self, message: protobuf::RequestMessage) {
+        let reply = if message.data.is_none() {
+            let code = protobuf::error_response::Code::EmptyRequest;
+            let error = "request has no data".to_string();
+            convert::error_message(code, error, message.request_id)
+        } else {
+            match self.handle_message(&message) {
+                None if message.request_id.is_empty() => return,
+                None => convert::ack_message(message.request_id),
+                reply => reply,
+            }
+        };
+
+        self.reply(reply);
+    }
+
+    fn reply(&mut self, reply: Option<EncodedMessage>) {
+        if let Some(reply) = reply {
+            let _ = petronel::Subscriber::send(&mut self.subscriber, &reply);
+        }
+    }
+
+    // Closes the connection if the client is sending requests too quickly
+    fn take_request_token(&mut self) -> bool {
+        let allowed = self.rate_limiter
+            .as_mut()
+            .map_or(true, TokenBucket::try_take);
+
+        if !allowed {
+            ServerMetrics::incr(&self.metrics.requests_rate_limited);
+            self.subscriber.close(1008, b"rate limit exceeded");
+        }
+
+        allowed
+    }
+
+    // Returns the reply to send, if the request has one other than an ack
+    fn handle_message(&mut self, message: &protobuf::RequestMessage) -> Option<EncodedMessage> {
         use protobuf::request_message::Data::*;
 
         let data = match message.data {
             Some(ref d) => d,
-            None => return,
+            None => return None,
         };
 
         match data {
-            &AllRaidBossesMessage(_) => subscription.get_bosses(),
+            &AllRaidBossesMessage(ref req) if req.since > 0 => {
+                // Answered from the directory, since petronel can only send everything
+                let changes = self.directory.changed_since(req.since);
+                return Some(convert::boss_list_message(
+                    changes.updated.iter(),
+                    &changes.removed,
+                    changes.sync_timestamp,
+                    self.boss_list_chunk_size,
+                    message.request_id.clone(),
+                ));
+            }
+            &AllRaidBossesMessage(_) => self.subscription.get_bosses(),
             &RaidBossesMessage(ref req) => for name in req.boss_names.iter() {
-                subscription.get_tweets(name)
-            },
-            &FollowMessage(ref req) => for boss_name in req.boss_names.iter() {
-                let name = BossName::from(boss_name);
-                subscription.follow(name.clone());
-                subscription.get_tweets(name);
+                self.subscription.get_tweets(name)
             },
-            &UnfollowMessage(ref req) => for name in req.boss_names.iter() {
-                subscription.unfollow(name)
+            &FollowMessage(ref req) => {
+                return self.follow(req)
+                    .and_then(|data| convert::reply_message(data, message.request_id.clone()))
+            }
+            &UnfollowMessage(ref req) => {
+                let mut translations = vec![];
+
+                for boss_name in req.boss_names.iter() {
+                    let name = BossName::from(boss_name);
+                    if self.subscriber.is_followed_as_translation(&name) {
+                        translations.push(boss_name.as_str());
+                        continue;
+                    }
+
+                    for unfollowed in self.subscriber.unfollow(&name, &self.directory) {
+                        self.subscription.unfollow(unfollowed);
+                    }
+                }
+
+                if !translations.is_empty() {
+                    let code = protobuf::error_response::Code::FollowedAsTranslation;
+                    let error = format!(
+                        "only followed as translations, unfollow the original bosses instead: {}",
+                        translations.join(", ")
+                    );
+                    return convert::error_message(code, error, message.request_id.clone());
+                }
+            }
+            &ConnectionOptionsMessage(ref req) => {
+                self.subscriber.set_dedupe_window(req.dedupe_window_seconds.max(0) as u64);
+            }
+        }
+
+        None
+    }
+
+    // Replies with a follow status if any of the names aren't known bosses
+    fn follow(
+        &mut self,
+        req: &protobuf::FollowRequest,
+    ) -> Option<protobuf::response_message::Data> {
+        if let Some(ref filter) = req.filter {
+            self.subscriber.set_filter(FollowFilter::from_proto(filter));
+        }
+
+        let mut followed_boss_names = vec![];
+        let mut unknown_bosses = vec![];
+
+        for boss_name in req.boss_names.iter() {
+            let name = BossName::from(boss_name);
+
+            if !self.directory.contains(&name) {
+                // Suggestions compare the name against every known boss, so
+                // only the first few unknown names in a request get them
+                let suggestions = if unknown_bosses.len() < MAX_SUGGESTED_NAMES {
+                    self.directory
+                        .suggestions(boss_name)
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect()
+                } else {
+                    vec![]
+                };
+
+                unknown_bosses.push(protobuf::UnknownBoss {
+                    name: boss_name.clone(),
+                    suggestions,
+                });
+
+                if self.reject_unknown_follows {
+                    continue;
+                }
+            }
+
+            let translations = self.subscriber
+                .follow(&name, req.follow_translations, &self.directory);
+
+            for translation in translations {
+                self.subscription.follow(translation.clone());
+                self.subscription.get_tweets(translation);
+            }
+
+            self.subscription.follow(name.clone());
+            self.subscription.get_tweets(name);
+            followed_boss_names.push(boss_name.clone());
+        }
+
+        if unknown_bosses.is_empty() {
+            return None;
+        }
+
+        Some(protobuf::response_message::Data::FollowStatusMessage(
+            protobuf::FollowStatusResponse {
+                followed_boss_names,
+                unknown_bosses,
             },
+        ))
+    }
+
+    // Pings the client, returning false if it has been silent for too long.
+    // A half-open connection would otherwise stay subscribed forever.
+    fn poll_ping(&mut self) -> Result<bool, ()> {
+        while let Async::Ready(Some(())) = self.ping_interval.poll().map_err(|_| ())? {
+            if self.last_activity.elapsed() > self.ping_timeout {
+                ServerMetrics::incr(&self.metrics.ping_timeouts);
+                self.subscriber.close(1001, b"ping timeout");
+                return Ok(false);
+            }
+
+            self.subscriber.ping()?;
         }
+
+        Ok(true)
     }
 }
 
 impl<S> Future for WebsocketReader<S>
 where
-    S: AsyncRead,
+    S: AsyncRead + AsyncWrite,
 {
     type Item = ();
     type Error = ();
 
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        if !self.poll_ping()? {
+            return Ok(Async::Ready(()));
+        }
+
+        while let Ok(Async::Ready(Some(name))) = self.new_translations.poll() {
+            self.subscription.follow(name);
+        }
+
         loop {
-            let amount_consumed = {
-                let (mut in_buf, subscription) =
-                    (&mut self.read_buf.in_buf, &mut self.subscription);
-
-                let parsed_frame =
-                    websocket::parse_frame(&mut in_buf, MAX_PACKET_SIZE, true).map_err(|_| ())?;
-
-                if let Some((frame, amount_consumed)) = parsed_frame {
-                    if let Frame::Binary(bytes) = frame {
-                        let message = protobuf::RequestMessage::decode(bytes).map_err(|_| ())?;
-                        Self::handle_message(subscription, &message);
-                    } else if let Frame::Pong(_) = frame {
-                        // Ignore
-                    } else {
-                        return Err(());
-                    };
-
-                    Some(amount_consumed)
-                } else {
-                    None
+            let max_frame_size = self.max_frame_size;
+            let request = match websocket::read_request(&mut self.read_buf.in_buf, max_frame_size) {
+                Ok(request) => request,
+                Err(RequestError::Frame(ErrorEnum::TooLong)) => {
+                    ServerMetrics::incr(&self.metrics.requests_too_large);
+                    self.subscriber.close(1009, b"message too big");
+                    return Ok(Async::Ready(()));
+                }
+                Err(RequestError::Decode(e, amount_consumed)) => {
+                    if !self.take_request_token() {
+                        return Ok(Async::Ready(()));
+                    }
+
+                    let code = protobuf::error_response::Code::MalformedRequest;
+                    self.reply(convert::error_message(code, e.to_string(), String::new()));
+                    self.read_buf.in_buf.consume(amount_consumed);
+                    continue;
+                }
+                Err(RequestError::Frame(_)) => {
+                    self.subscriber.close(1002, b"protocol error");
+                    return Ok(Async::Ready(()));
+                }
+                Err(RequestError::UnexpectedFrame) => {
+                    // Requests are only accepted as binary frames
+                    self.subscriber.close(1003, b"unexpected frame");
+                    return Ok(Async::Ready(()));
+                }
+            };
+
+            let amount_consumed = if let Some((incoming, amount_consumed)) = request {
+                match incoming {
+                    Incoming::Request(message) => {
+                        if !self.take_request_token() {
+                            return Ok(Async::Ready(()));
+                        }
+
+                        self.handle_request(message);
+                    }
+                    Incoming::Ping(payload) => {
+                        if !self.take_request_token() {
+                            return Ok(Async::Ready(()));
+                        }
+
+                        self.subscriber.pong(&payload)?;
+                    }
+                    Incoming::Close(code) => {
+                        // Echo the client's code, unless it's one that can't be sent
+                        let code = match code {
+                            1005 | 1006 | 1015 => 1000,
+                            code => code,
+                        };
+                        self.subscriber.close(code, b"");
+                        return Ok(Async::Ready(()));
+                    }
+                    Incoming::Pong => {}
                 }
+
+                Some(amount_consumed)
+            } else {
+                None
             };
 
             if let Some(amount) = amount_consumed {
@@ -239,12 +970,12 @@ where
             } else {
                 let bytes_read = self.read_buf.read().map_err(|_| ())?;
 
-                if bytes_read == 0 {
-                    if self.read_buf.done() {
-                        return Ok(Async::Ready(()));
-                    } else {
-                        return Ok(Async::NotReady);
-                    }
+                if bytes_read > 0 {
+                    self.last_activity = Instant::now();
+                } else if self.read_buf.done() {
+                    return Ok(Async::Ready(()));
+                } else {
+                    return Ok(Async::NotReady);
                 }
             }
         }
@@ -0,0 +1,100 @@
+use brotli;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+// Responses smaller than this aren't worth compressing
+const MIN_COMPRESS_SIZE: usize = 1024;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+// Compression runs on the reactor thread, so this trades a little size for
+// speed (about as fast as gzip's default level)
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    // Picks the best encoding we support from an `Accept-Encoding` header,
+    // preferring brotli over gzip when the client has no preference
+    pub(crate) fn negotiate(accept_encoding: Option<&str>) -> Self {
+        let accept_encoding = match accept_encoding {
+            Some(value) => value,
+            None => return Encoding::Identity,
+        };
+
+        let mut best = (Encoding::Identity, 0.0);
+
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let encoding = match params.next() {
+                Some(name) if name.eq_ignore_ascii_case("br") => Encoding::Brotli,
+                Some(name) if name.eq_ignore_ascii_case("gzip") => Encoding::Gzip,
+                _ => continue,
+            };
+
+            let quality = params
+                .filter_map(|param| {
+                    if param.starts_with("q=") {
+                        param[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+
+            let is_better = quality > best.1
+                || (quality == best.1 && encoding == Encoding::Brotli && quality > 0.0);
+
+            if is_better {
+                best = (encoding, quality);
+            }
+        }
+
+        best.0
+    }
+
+    // The encoding to actually use for a body of the given length
+    pub(crate) fn for_len(self, len: usize) -> Self {
+        if len < MIN_COMPRESS_SIZE {
+            Encoding::Identity
+        } else {
+            self
+        }
+    }
+
+    pub(crate) fn content_encoding(&self) -> Option<&'static str> {
+        match *self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Brotli => Some("br"),
+        }
+    }
+
+    pub(crate) fn compress(&self, body: &[u8]) -> Vec<u8> {
+        match *self {
+            Encoding::Identity => body.to_vec(),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW_SIZE,
+                );
+                writer.write_all(body).unwrap();
+                writer.into_inner()
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use cors::CorsConfig;
+use expiry::ExpiryPolicy;
+use limits::LimitsConfig;
+use origin::OriginList;
+use petronel::error::*;
+use proxy::ProxyConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tls::TlsConfig;
+
+pub(crate) const DEFAULT_BOSS_LIST_CHUNK_BYTES: usize = 256 * 1024;
+
+// Settings that tests need are public, so that they can build a `Config`
+// directly instead of going through the environment
+pub struct Config {
+    pub bind_address: SocketAddr,
+    pub heartbeat_interval: Duration,
+    // How often websocket clients are sent Ping frames
+    pub ping_interval: Duration,
+    // Clients that send nothing (including Pongs) for this long are disconnected
+    pub ping_timeout: Duration,
+    // Boss lists larger than this many bytes are split into several responses
+    pub boss_list_chunk_size: usize,
+    // Whether follows for bosses that haven't been seen yet are refused,
+    // rather than kept in case the boss shows up later
+    pub reject_unknown_follows: bool,
+    // Base URL to connect to instead of Twitter's streaming API (e.g., for tests)
+    pub twitter_stream_url: Option<String>,
+    pub(crate) redis_url: Option<String>,
+    pub(crate) expiry: ExpiryPolicy,
+    pub limits: LimitsConfig,
+    pub proxy: ProxyConfig,
+    pub(crate) tls: Option<TlsConfig>,
+    // Websocket handshakes from other origins are rejected. If unset, all
+    // origins are allowed. Requests without an `Origin` header (i.e., not
+    // from a browser) are always allowed.
+    pub(crate) allowed_origins: Option<OriginList>,
+    pub cors: Option<CorsConfig>,
+    // How long `/api/bosses.json` responses can be cached, by us and by clients
+    pub(crate) bosses_cache_max_age_seconds: u64,
+    // Bearer token for `/api/admin/` endpoints, which are disabled if unset
+    pub admin_token: Option<String>,
+    // Directory containing the frontend, which isn't served if unset
+    pub static_dir: Option<PathBuf>,
+    // Cache lifetime for static files other than index.html
+    pub(crate) static_max_age_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: "0.0.0.0:8080".parse().unwrap(),
+            heartbeat_interval: Duration::from_secs(30),
+            ping_interval: Duration::from_secs(30),
+            ping_timeout: Duration::from_secs(90),
+            boss_list_chunk_size: DEFAULT_BOSS_LIST_CHUNK_BYTES,
+            reject_unknown_follows: false,
+            twitter_stream_url: None,
+            redis_url: None,
+            expiry: ExpiryPolicy::default(),
+            limits: LimitsConfig::default(),
+            proxy: ProxyConfig::default(),
+            tls: None,
+            allowed_origins: None,
+            cors: None,
+            bosses_cache_max_age_seconds: 30,
+            admin_token: None,
+            static_dir: None,
+            static_max_age_seconds: 3600,
+        }
+    }
+}
+
+impl Config {
+    // STATIC_DIR: directory containing the frontend, disabled if unset
+    // STATIC_MAX_AGE_SECONDS: cache lifetime for files other than index.html
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+
+        let bind_address = match env_opt("BIND_ADDRESS") {
+            Some(address) => address
+                .parse()
+                .chain_err(|| "invalid value for BIND_ADDRESS environment variable")?,
+            None => default.bind_address,
+        };
+
+        Ok(Config {
+            bind_address,
+            heartbeat_interval: Duration::from_secs(env_parse(
+                "HEARTBEAT_INTERVAL_SECONDS",
+                default.heartbeat_interval.as_secs(),
+            )?),
+            ping_interval: Duration::from_secs(env_parse(
+                "PING_INTERVAL_SECONDS",
+                default.ping_interval.as_secs(),
+            )?),
+            ping_timeout: Duration::from_secs(env_parse(
+                "PING_TIMEOUT_SECONDS",
+                default.ping_timeout.as_secs(),
+            )?),
+            boss_list_chunk_size: env_parse(
+                "BOSS_LIST_CHUNK_BYTES",
+                default.boss_list_chunk_size,
+            )?,
+            reject_unknown_follows: env_parse(
+                "REJECT_UNKNOWN_FOLLOWS",
+                default.reject_unknown_follows,
+            )?,
+            twitter_stream_url: env_opt("TWITTER_STREAM_URL"),
+            redis_url: env_opt("REDIS_URL"),
+            expiry: ExpiryPolicy::from_env()?,
+            limits: LimitsConfig::from_env()?,
+            proxy: ProxyConfig::from_env()?,
+            tls: TlsConfig::from_env()?,
+            allowed_origins: OriginList::from_env("ALLOWED_ORIGINS"),
+            cors: CorsConfig::from_env()?,
+            bosses_cache_max_age_seconds: env_parse(
+                "BOSSES_CACHE_MAX_AGE_SECONDS",
+                default.bosses_cache_max_age_seconds,
+            )?,
+            admin_token: env_opt("ADMIN_TOKEN"),
+            static_dir: env_opt("STATIC_DIR").map(PathBuf::from),
+            static_max_age_seconds: env_parse(
+                "STATIC_MAX_AGE_SECONDS",
+                default.static_max_age_seconds,
+            )?,
+        })
+    }
+}
+
+pub(crate) fn env(name: &str) -> Result<String> {
+    ::std::env::var(name).chain_err(|| format!("invalid value for {} environment variable", name))
+}
+
+pub(crate) fn env_opt(name: &str) -> Option<String> {
+    ::std::env::var(name).ok().and_then(|value| {
+        let value = value.trim();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    })
+}
+
+pub(crate) fn env_parse<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+{
+    match env_opt(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid value for {} environment variable", name).into()),
+        None => Ok(default),
+    }
+}
+
+// Comma-separated list, with surrounding whitespace and empty entries removed
+pub(crate) fn env_list(name: &str) -> Vec<String> {
+    env_opt(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
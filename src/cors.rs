@@ -0,0 +1,70 @@
+use config;
+use origin::{self, OriginList};
+use petronel::error::*;
+use tk_http::server::{Encoder, Head};
+
+pub struct CorsConfig {
+    pub allowed_origins: OriginList,
+    // How long browsers may cache preflight responses
+    pub max_age_seconds: u64,
+}
+
+impl CorsConfig {
+    // CORS_ALLOWED_ORIGINS: comma-separated origins allowed to call the JSON
+    //   API (`*` wildcards allowed). CORS is disabled if unset.
+    // CORS_MAX_AGE_SECONDS: how long browsers may cache preflight responses
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let allowed_origins = match OriginList::from_env("CORS_ALLOWED_ORIGINS") {
+            Some(origins) => origins,
+            None => return Ok(None),
+        };
+
+        Ok(Some(CorsConfig {
+            allowed_origins,
+            max_age_seconds: config::env_parse("CORS_MAX_AGE_SECONDS", 86400)?,
+        }))
+    }
+
+    // Returns the CORS headers to add to the response. The request's origin
+    // is only allowed if it's in the list.
+    pub(crate) fn for_request(&self, headers: &Head) -> CorsHeaders {
+        let allowed_origin = match origin::origin_header(headers) {
+            Some(ref origin) if self.allowed_origins.allows(origin) => Some(origin.clone()),
+            _ => None,
+        };
+
+        CorsHeaders {
+            allowed_origin,
+            max_age_seconds: self.max_age_seconds,
+        }
+    }
+}
+
+// Request headers that browsers may send to the API from other origins
+const ALLOWED_HEADERS: &str = "Content-Type, If-None-Match";
+
+#[derive(Clone)]
+pub(crate) struct CorsHeaders {
+    allowed_origin: Option<String>,
+    max_age_seconds: u64,
+}
+
+impl CorsHeaders {
+    pub(crate) fn add_to<S>(&self, e: &mut Encoder<S>) {
+        // Sent even if the origin isn't allowed, since responses can be
+        // cached and the next request might come from an allowed origin
+        e.add_header("Vary", "Origin").unwrap();
+        if let Some(ref origin) = self.allowed_origin {
+            e.add_header("Access-Control-Allow-Origin", origin).unwrap();
+        }
+    }
+
+    pub(crate) fn add_preflight_to<S>(&self, e: &mut Encoder<S>) {
+        self.add_to(e);
+        if self.allowed_origin.is_some() {
+            e.add_header("Access-Control-Allow-Methods", "GET, OPTIONS").unwrap();
+            e.add_header("Access-Control-Allow-Headers", ALLOWED_HEADERS).unwrap();
+            e.format_header("Access-Control-Max-Age", self.max_age_seconds).unwrap();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Longer windows requested by clients are shortened to this, since every
+// raid seen within the window is remembered
+pub(crate) const MAX_WINDOW_SECONDS: u64 = 10 * 60;
+
+// Raid IDs recently sent to a connection, so that repeated tweets for the
+// same raid (retweets, or the same raid posted in both languages) can be
+// reported as duplicates instead of being sent again.
+pub(crate) struct RaidDeduper {
+    window: Duration,
+    counts: HashMap<String, u32>,
+    // Raid IDs in the order they were first seen
+    order: VecDeque<(Instant, String)>,
+}
+
+impl RaidDeduper {
+    pub(crate) fn new(window: Duration) -> Self {
+        RaidDeduper {
+            window: cmp::min(window, Duration::from_secs(MAX_WINDOW_SECONDS)),
+            counts: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // If the raid was already sent within the window, counts another copy
+    // and returns how many have been seen, including the first
+    pub(crate) fn record_duplicate(&mut self, raid_id: &str, now: Instant) -> Option<u32> {
+        self.expire(now);
+
+        self.counts.get_mut(raid_id).map(|count| {
+            *count += 1;
+            *count
+        })
+    }
+
+    // Called once the first tweet for a raid has actually been queued
+    pub(crate) fn record_sent(&mut self, raid_id: &str, now: Instant) {
+        if !self.counts.contains_key(raid_id) {
+            self.counts.insert(raid_id.to_string(), 1);
+            self.order.push_back((now, raid_id.to_string()));
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        loop {
+            let is_expired = match self.order.front() {
+                Some(&(first_seen, _)) => now.duration_since(first_seen) >= self.window,
+                None => false,
+            };
+
+            if !is_expired {
+                break;
+            }
+
+            if let Some((_, raid_id)) = self.order.pop_front() {
+                self.counts.remove(&raid_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use chrono::Utc;
+use petronel::model::{BossName, RaidBoss, RaidBossMetadata};
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// How many suggestions to give for an unknown boss name, and how different
+// from it they can be
+const MAX_SUGGESTIONS: usize = 3;
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+// How long removed bosses are remembered, so that incremental boss lists can
+// tell clients about them. Clients that last synced before that need a full list.
+pub(crate) const REMOVED_RETENTION_MILLISECONDS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+struct Entry {
+    boss: RaidBoss,
+    // When we last saw this boss change, in milliseconds since the epoch
+    updated_at: i64,
+}
+
+struct Bosses {
+    known: HashMap<BossName, Entry>,
+    // When each boss was removed, in milliseconds since the epoch
+    removed: HashMap<BossName, i64>,
+}
+
+// Bosses that changed since an incremental boss list request's `since`
+pub(crate) struct Changes {
+    pub(crate) updated: Vec<RaidBoss>,
+    pub(crate) removed: Vec<BossName>,
+    // Taken before the bosses were read, so that anything that changes
+    // afterwards is included next time
+    pub(crate) sync_timestamp: i64,
+}
+
+// Our own copy of every known boss, kept up to date from the messages
+// petronel sends to subscribers. This lets connections look bosses up and
+// answer incremental boss list requests without going through petronel.
+//
+// Timestamps are taken while holding the lock, so a change is never stamped
+// earlier than a `Changes` that doesn't include it.
+#[derive(Clone)]
+pub(crate) struct BossDirectory {
+    bosses: Arc<RwLock<Bosses>>,
+}
+
+impl BossDirectory {
+    pub(crate) fn new(initial_bosses: &[RaidBossMetadata]) -> Self {
+        let known = initial_bosses
+            .iter()
+            .map(|meta| {
+                let entry = Entry {
+                    boss: meta.boss.clone(),
+                    updated_at: meta.last_seen.timestamp() * 1000,
+                };
+                (meta.boss.name.clone(), entry)
+            })
+            .collect();
+
+        let bosses = Bosses {
+            known,
+            removed: HashMap::new(),
+        };
+
+        BossDirectory {
+            bosses: Arc::new(RwLock::new(bosses)),
+        }
+    }
+
+    // A boss was added or changed
+    pub(crate) fn update(&self, boss: &RaidBoss) {
+        let mut bosses = self.bosses.write().unwrap();
+        let entry = Entry {
+            boss: boss.clone(),
+            updated_at: now_as_milliseconds(),
+        };
+
+        bosses.removed.remove(&boss.name);
+        bosses.known.insert(boss.name.clone(), entry);
+    }
+
+    // Adds any bosses from a full list that we somehow missed. Existing
+    // entries keep their timestamps.
+    pub(crate) fn sync<'a, I>(&self, bosses: I)
+    where
+        I: IntoIterator<Item = &'a RaidBoss>,
+    {
+        let mut directory = self.bosses.write().unwrap();
+        let now = now_as_milliseconds();
+
+        for boss in bosses {
+            if !directory.known.contains_key(&boss.name) {
+                let entry = Entry {
+                    boss: boss.clone(),
+                    updated_at: now,
+                };
+                directory.removed.remove(&boss.name);
+                directory.known.insert(boss.name.clone(), entry);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&self, name: &BossName) {
+        let mut bosses = self.bosses.write().unwrap();
+        let now = now_as_milliseconds();
+
+        if bosses.known.remove(name).is_some() {
+            bosses.removed.insert(name.clone(), now);
+        }
+
+        let expired = bosses
+            .removed
+            .iter()
+            .filter(|&(_, &removed_at)| now - removed_at > REMOVED_RETENTION_MILLISECONDS)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in expired {
+            bosses.removed.remove(&name);
+        }
+    }
+
+    pub(crate) fn contains(&self, name: &BossName) -> bool {
+        self.bosses.read().unwrap().known.contains_key(name)
+    }
+
+    // Known boss names that look like the given one, either differing only
+    // in case or within a few edits. Closest first.
+    pub(crate) fn suggestions(&self, name: &str) -> Vec<BossName> {
+        let name = name.to_lowercase();
+        let name_len = name.chars().count();
+
+        let mut matches = self.bosses
+            .read()
+            .unwrap()
+            .known
+            .keys()
+            .filter_map(|known| {
+                let known_name = known.to_string().to_lowercase();
+                // The distance is at least the difference in length, so this
+                // skips most bosses without computing it
+                let known_len = known_name.chars().count();
+                let len_difference = cmp::max(name_len, known_len) - cmp::min(name_len, known_len);
+                if len_difference > MAX_SUGGESTION_DISTANCE {
+                    return None;
+                }
+
+                let distance = edit_distance(&name, &known_name);
+                if distance <= MAX_SUGGESTION_DISTANCE {
+                    Some((distance, known.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        matches.sort_by_key(|&(distance, ref known)| (distance, known.to_string()));
+        matches
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, known)| known)
+            .collect()
+    }
+
+    // Cheaper than `get` when only the level is needed, e.g. for every tweet
+    pub(crate) fn level(&self, name: &BossName) -> Option<i16> {
+        self.bosses
+            .read()
+            .unwrap()
+            .known
+            .get(name)
+            .map(|entry| entry.boss.level)
+    }
+
+    pub(crate) fn get(&self, name: &BossName) -> Option<RaidBoss> {
+        self.bosses
+            .read()
+            .unwrap()
+            .known
+            .get(name)
+            .map(|entry| entry.boss.clone())
+    }
+
+    // Bosses that changed or were removed at or after the given time, in
+    // milliseconds
+    pub(crate) fn changed_since(&self, since: i64) -> Changes {
+        let bosses = self.bosses.read().unwrap();
+        let sync_timestamp = now_as_milliseconds();
+
+        let updated = bosses
+            .known
+            .values()
+            .filter(|entry| entry.updated_at >= since)
+            .map(|entry| entry.boss.clone())
+            .collect();
+
+        let removed = bosses
+            .removed
+            .iter()
+            .filter(|&(_, &removed_at)| removed_at >= since)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        Changes {
+            updated,
+            removed,
+            sync_timestamp,
+        }
+    }
+}
+
+// Levenshtein distance, counted in characters rather than bytes
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..b.len() + 1).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = Vec::with_capacity(b.len() + 1);
+        current.push(i + 1);
+
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == b_char { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(cmp::min(substitution, cmp::min(insertion, deletion)));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+pub(crate) fn now_as_milliseconds() -> i64 {
+    let now = Utc::now();
+    now.timestamp() * 1000 + now.timestamp_subsec_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use petronel::model::Language;
+    use std::collections::HashSet;
+
+    fn raid_boss(name: &str) -> RaidBoss {
+        RaidBoss {
+            name: name.to_string().into(),
+            level: 60,
+            image: None,
+            language: Language::English,
+            translations: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn removed_bosses_are_included_in_changes() {
+        let directory = BossDirectory::new(&[]);
+        let boss = raid_boss("Lvl 60 Ozorotter");
+        directory.update(&boss);
+
+        let since = directory.changed_since(0).sync_timestamp;
+        directory.remove(&boss.name);
+
+        let changes = directory.changed_since(since);
+        assert!(changes.updated.is_empty());
+        assert_eq!(changes.removed, vec![boss.name.clone()]);
+        assert!(changes.sync_timestamp >= since);
+
+        // Seen again, so no longer removed
+        directory.update(&boss);
+        let changes = directory.changed_since(since);
+        assert_eq!(changes.updated.len(), 1);
+        assert!(changes.removed.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Duration, Utc};
+use config;
+use petronel::error::*;
+use petronel::model::{BossName, RaidBossMetadata};
+use std::collections::HashSet;
+use std::i64;
+
+// Used when BOSS_EXPIRY_RULES isn't set
+const DEFAULT_RULES: &str = "-99=3d,100-=30d";
+
+// Bosses whose level falls within `min_level..=max_level` expire after
+// they haven't been seen for `max_age`
+struct ExpiryRule {
+    min_level: Option<i16>,
+    max_level: Option<i16>,
+    max_age: Duration,
+}
+
+impl ExpiryRule {
+    // Format is `<min>-<max>=<duration>`, where either bound may be omitted
+    // (e.g. `100-=30d`), or `<level>=<duration>` for a single level.
+    fn parse(rule: &str) -> Result<Self> {
+        let mut parts = rule.splitn(2, '=');
+        let range = parts.next().unwrap_or("").trim();
+        let max_age = match parts.next() {
+            Some(duration) => parse_duration(duration.trim())?,
+            None => bail!("missing duration in boss expiry rule `{}`", rule),
+        };
+
+        let parse_level = |level: &str| -> Result<Option<i16>> {
+            if level.is_empty() {
+                Ok(None)
+            } else {
+                level
+                    .parse()
+                    .map(Some)
+                    .chain_err(|| format!("invalid level in boss expiry rule `{}`", rule))
+            }
+        };
+
+        let (min_level, max_level) = if range.contains('-') {
+            let mut bounds = range.splitn(2, '-');
+            let min = parse_level(bounds.next().unwrap_or("").trim())?;
+            let max = parse_level(bounds.next().unwrap_or("").trim())?;
+            (min, max)
+        } else {
+            let level = parse_level(range)?;
+            (level, level)
+        };
+
+        Ok(ExpiryRule {
+            min_level,
+            max_level,
+            max_age,
+        })
+    }
+
+    fn matches(&self, level: i16) -> bool {
+        self.min_level.map_or(true, |min| level >= min)
+            && self.max_level.map_or(true, |max| level <= max)
+    }
+}
+
+// Accepts a number followed by a unit (`d`, `h`, `m` or `s`)
+fn parse_duration(s: &str) -> Result<Duration> {
+    let unit_len = s.chars().last().map_or(0, char::len_utf8);
+    let (amount, unit) = s.split_at(s.len() - unit_len);
+    let amount = amount
+        .parse::<i64>()
+        .chain_err(|| format!("invalid duration `{}`", s))?;
+
+    let seconds_per_unit = match unit {
+        "d" => 24 * 60 * 60,
+        "h" => 60 * 60,
+        "m" => 60,
+        "s" => 1,
+        _ => bail!("invalid duration `{}` (expected a unit of d, h, m or s)", s),
+    };
+
+    // `Duration` panics on anything that doesn't fit in i64 milliseconds
+    let max_seconds = i64::MAX / 1000;
+    match amount.checked_mul(seconds_per_unit) {
+        Some(seconds) if -max_seconds <= seconds && seconds <= max_seconds => {
+            Ok(Duration::seconds(seconds))
+        }
+        _ => bail!("duration `{}` is too long", s),
+    }
+}
+
+fn parse_rules(rules: &str) -> Result<Vec<ExpiryRule>> {
+    rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(ExpiryRule::parse)
+        .collect()
+}
+
+pub(crate) struct ExpiryPolicy {
+    rules: Vec<ExpiryRule>,
+    pinned: HashSet<BossName>,
+    denied: HashSet<BossName>,
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        ExpiryPolicy {
+            rules: parse_rules(DEFAULT_RULES).expect("invalid default boss expiry rules"),
+            pinned: HashSet::new(),
+            denied: HashSet::new(),
+        }
+    }
+}
+
+impl ExpiryPolicy {
+    // BOSS_EXPIRY_RULES: comma-separated rules, first matching rule wins.
+    //   Bosses that don't match any rule never expire.
+    // BOSS_EXPIRY_PINNED: comma-separated boss names that never expire
+    // BOSS_EXPIRY_DENIED: comma-separated boss names that are always removed
+    pub(crate) fn from_env() -> Result<Self> {
+        let rules = config::env_opt("BOSS_EXPIRY_RULES").unwrap_or(DEFAULT_RULES.to_string());
+        let rules = parse_rules(&rules)
+            .chain_err(|| "invalid value for BOSS_EXPIRY_RULES environment variable")?;
+
+        let to_names = |names: Vec<String>| names.iter().map(BossName::from).collect();
+
+        Ok(ExpiryPolicy {
+            rules,
+            pinned: to_names(config::env_list("BOSS_EXPIRY_PINNED")),
+            denied: to_names(config::env_list("BOSS_EXPIRY_DENIED")),
+        })
+    }
+
+    pub(crate) fn is_expired(&self, meta: &RaidBossMetadata, now: DateTime<Utc>) -> bool {
+        let name = &meta.boss.name;
+
+        if self.denied.contains(name) {
+            return true;
+        }
+
+        if self.pinned.contains(name) {
+            return false;
+        }
+
+        let last_seen_duration = now.signed_duration_since(meta.last_seen);
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(meta.boss.level))
+            .map_or(false, |rule| last_seen_duration > rule.max_age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_too_long_for_chrono_are_errors() {
+        assert!(ExpiryRule::parse("1-=9999999999999d").is_err());
+        assert!(ExpiryRule::parse("1-=-9999999999999d").is_err());
+        assert!(ExpiryRule::parse("1-=9223372036854775807s").is_err());
+        assert!(ExpiryRule::parse("1-=-9223372036854775808s").is_err());
+        assert_eq!(parse_duration("30d").unwrap(), Duration::days(30));
+    }
+}
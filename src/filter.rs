@@ -0,0 +1,46 @@
+use petronel::model::Language;
+use protobuf;
+use protobuf::convert::language_from_proto;
+use std::cmp;
+use std::i16;
+
+// What a broadcast message is about, for deciding which connections want it
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FilterKey {
+    pub(crate) language: Language,
+    // Unknown for tweets about bosses we haven't seen an update for yet
+    pub(crate) level: Option<i16>,
+}
+
+// Set by the client in a follow request, and applied to boss updates and
+// followed tweets before they're queued for the connection
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FollowFilter {
+    language: Option<Language>,
+    min_level: i16,
+}
+
+impl FollowFilter {
+    pub(crate) fn from_proto(filter: &protobuf::FollowFilter) -> Self {
+        let language = match language_from_proto(filter.language) {
+            Language::Other => None,
+            language => Some(language),
+        };
+
+        // Boss levels are 16 bits, so anything outside that range is clamped
+        // rather than wrapped around
+        let min_level = cmp::max(i16::MIN as i32, cmp::min(filter.min_level, i16::MAX as i32));
+
+        FollowFilter {
+            language,
+            min_level: min_level as i16,
+        }
+    }
+
+    pub(crate) fn allows(&self, key: &FilterKey) -> bool {
+        let language_matches = self.language.map_or(true, |lang| lang == key.language);
+        let level_matches = key.level.map_or(true, |level| level >= self.min_level);
+
+        language_matches && level_matches
+    }
+}
@@ -0,0 +1,48 @@
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+
+// Encoded websocket frames for recent tweets, keyed by tweet ID, so that each
+// tweet is only serialized once no matter how often it's requested. When
+// full, the oldest tweets are evicted first.
+pub struct FrameCache {
+    capacity: usize,
+    frames: HashMap<u64, Bytes>,
+    order: VecDeque<u64>,
+}
+
+impl FrameCache {
+    pub fn new(capacity: usize) -> Self {
+        FrameCache {
+            capacity,
+            frames: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn get_or_insert_with<F>(&mut self, tweet_id: u64, encode: F) -> Option<Bytes>
+    where
+        F: FnOnce() -> Option<Bytes>,
+    {
+        if let Some(frame) = self.frames.get(&tweet_id) {
+            return Some(frame.clone());
+        }
+
+        let frame = match encode() {
+            Some(frame) => frame,
+            None => return None,
+        };
+
+        if self.capacity > 0 {
+            if self.order.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.frames.remove(&oldest);
+                }
+            }
+
+            self.order.push_back(tweet_id);
+            self.frames.insert(tweet_id, frame.clone());
+        }
+
+        Some(frame)
+    }
+}
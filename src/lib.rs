@@ -0,0 +1,259 @@
+#[macro_use]
+extern crate error_chain;
+#[macro_use]
+extern crate futures;
+#[macro_use]
+extern crate prost_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
+
+extern crate byteorder;
+extern crate brotli;
+extern crate bytes;
+extern crate chrono;
+extern crate flate2;
+extern crate futures_cpupool;
+extern crate hyper;
+extern crate hyper_tls;
+extern crate petronel;
+extern crate prost;
+extern crate redis;
+extern crate rustls;
+#[macro_use]
+extern crate serde_json;
+extern crate tk_bufstream;
+extern crate tk_http;
+extern crate tk_listen;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_rustls;
+extern crate tokio_service;
+extern crate tokio_signal;
+
+mod api_cache;
+mod assets;
+mod persistence;
+mod config;
+mod cors;
+mod dedupe;
+mod directory;
+mod error;
+mod expiry;
+mod filter;
+mod limits;
+mod metrics;
+mod origin;
+mod proxy;
+mod server;
+mod stream;
+mod tls;
+mod twitter;
+pub mod protobuf;
+mod codec;
+mod compression;
+// Public with the `bench` feature, for the benchmarks and fuzz targets
+#[cfg(feature = "bench")]
+pub mod frame_cache;
+#[cfg(not(feature = "bench"))]
+mod frame_cache;
+#[cfg(feature = "bench")]
+pub mod outbox;
+#[cfg(not(feature = "bench"))]
+mod outbox;
+#[cfg(feature = "bench")]
+pub mod websocket;
+#[cfg(not(feature = "bench"))]
+mod websocket;
+
+#[cfg(feature = "bench")]
+pub mod bench;
+pub use config::Config;
+pub use cors::CorsConfig;
+pub use limits::{LimitsConfig, OutputLimits, SlowConsumerPolicy};
+pub use origin::OriginList;
+pub use proxy::{ProxyConfig, ProxyMode};
+
+use chrono::Utc;
+use config::env;
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use petronel::{ClientBuilder, Token};
+use petronel::error::*;
+use std::net;
+use std::sync::Arc;
+use std::time::Duration;
+use tk_http::server::Config as HttpConfig;
+use tokio_core::reactor::{Core, Interval, Timeout};
+
+const REDIS_TIMEOUT_SECONDS: u64 = 5;
+const CACHE_FLUSH_INTERVAL_SECONDS: u64 = 60 * 3;
+const TWEET_HISTORY_SIZE: usize = 15;
+// Encoded frames are kept for this many recent tweets, across all bosses
+const TWEET_FRAME_CACHE_SIZE: usize = 4096;
+
+// Runs the server with Twitter credentials and configuration read from
+// environment variables
+pub fn run_from_env() -> Result<()> {
+    let token = Token::new(
+        env("CONSUMER_KEY")?,
+        env("CONSUMER_SECRET")?,
+        env("ACCESS_TOKEN")?,
+        env("ACCESS_TOKEN_SECRET")?,
+    );
+
+    let config = Config::from_env()?;
+
+    let listener = net::TcpListener::bind(&config.bind_address)
+        .chain_err(|| "failed to bind TCP listener")?;
+
+    run(token, config, listener)
+}
+
+pub fn run(token: Token, config: Config, listener: net::TcpListener) -> Result<()> {
+    let config = Arc::new(config);
+
+    let mut core = Core::new().chain_err(|| "failed to create Core")?;
+    let handle = core.handle();
+
+    let bind_address = listener
+        .local_addr()
+        .chain_err(|| "failed to get listener address")?;
+    let listener = tokio_core::net::TcpListener::from_listener(listener, &bind_address, &handle)
+        .chain_err(|| "failed to bind TCP listener")?;
+
+    let connector = twitter::StreamConnector::new(config.twitter_stream_url.as_ref(), &handle)?;
+    let hyper_client = hyper::Client::configure()
+        .keep_alive(connector.keep_alive())
+        .connector(connector)
+        .build(&handle);
+
+    let cpu_pool = futures_cpupool::CpuPool::new_num_cpus();
+
+    let redis_url = config.redis_url.clone();
+    let (initial_bosses, cache_client, cache_worker) = if let Some(redis_url) = redis_url {
+        let (cache_client, cache_worker) = persistence::AsyncCache::new(
+            &cpu_pool,
+            redis_url,
+            "petronel_bosses".to_string(),
+            Some("bosses".to_string()),
+        );
+
+        let redis_timeout = Timeout::new(Duration::new(REDIS_TIMEOUT_SECONDS, 0), &handle).unwrap();
+
+        // Wow, timeouts are incredibly annoying to use...
+        let initial_bosses = match core.run(cache_client.get_bosses().select2(redis_timeout)) {
+            Ok(Either::A((bosses, _))) => bosses,
+            Ok(Either::B((_timeout, _))) => bail!(
+                "could not connect to Redis (timed out after {} seconds)",
+                REDIS_TIMEOUT_SECONDS
+            ),
+            Err(Either::A((err, _))) => Err(err)?,
+            Err(Either::B((_err, _))) => unreachable!(),
+        };
+
+        (initial_bosses, cache_client, cache_worker)
+    } else {
+        eprintln!("REDIS_URL environment variable not set, caching disabled");
+        let (cache_client, cache_worker) = persistence::AsyncCache::no_op(&cpu_pool);
+
+        (Vec::new(), cache_client, cache_worker)
+    };
+
+    let now = Utc::now();
+    let initial_bosses = initial_bosses
+        .into_iter()
+        .filter(|meta| !config.expiry.is_expired(meta, now))
+        .collect::<Vec<_>>();
+
+    let directory = directory::BossDirectory::new(&initial_bosses);
+    let encoder = protobuf::convert::MessageEncoder::new(
+        TWEET_FRAME_CACHE_SIZE,
+        directory.clone(),
+        config.boss_list_chunk_size,
+    );
+    let (petronel_client, petronel_worker) =
+        ClientBuilder::from_hyper_client(&hyper_client, &token)
+            .with_history_size(TWEET_HISTORY_SIZE)
+            .with_subscriber::<codec::WebsocketSubscriber>()
+            .filter_map_message(move |msg| encoder.encode(msg))
+            .with_bosses(initial_bosses)
+            .with_metrics(petronel::metrics::simple(|ref m| {
+                serde_json::to_vec(&m).unwrap()
+            }))
+            .build();
+
+    // Flush cache periodically
+    let cache_petronel_client = petronel_client.clone();
+    let cache_config = config.clone();
+    let cache_flush = Interval::new(Duration::new(CACHE_FLUSH_INTERVAL_SECONDS, 0), &handle)
+        .unwrap()
+        .then(|r| r.chain_err(|| "failed to create Interval"))
+        .and_then(move |_| {
+            let config = cache_config.clone();
+            cache_petronel_client.remove_bosses(move |meta| {
+                config.expiry.is_expired(meta, Utc::now())
+            });
+            cache_petronel_client.export_metadata()
+        })
+        .for_each(move |data| Ok(cache_client.update_bosses(data)))
+        .then(|r| r.chain_err(|| "cache flush failed"))
+        .join(cache_worker);
+
+    // Send heartbeats periodically
+    let heartbeat_petronel_client = petronel_client.clone();
+    let heartbeat = Interval::new(config.heartbeat_interval, &handle)
+        .chain_err(|| "failed to create Interval")?
+        .for_each(move |_| Ok(heartbeat_petronel_client.heartbeat()))
+        .then(|r| r.chain_err(|| "heartbeat failed"));
+
+    let metrics = Arc::new(metrics::ServerMetrics::default());
+    let connection_limiter = limits::ConnectionLimiter::new(config.limits.max_connections_per_ip);
+
+    let state = server::HandlerState {
+        http_config: HttpConfig::new().done(),
+        petronel_client,
+        config: config.clone(),
+        metrics,
+        connection_limiter,
+        bosses_cache: api_cache::ResponseCache::new(config.bosses_cache_max_age_seconds),
+        assets: match config.static_dir {
+            Some(ref dir) => Some(Arc::new(assets::StaticAssets::load(
+                dir,
+                config.static_max_age_seconds,
+            )?)),
+            None => None,
+        },
+        directory,
+    };
+
+    let handler = server::ConnectionHandler {
+        handle: handle.clone(),
+        state,
+    };
+
+    let tls_server = match config.tls {
+        Some(ref tls_config) => {
+            let acceptor = tls::TlsAcceptor::new(tls_config)?;
+            let tls_listener =
+                tokio_core::net::TcpListener::bind(&tls_config.bind_address, &handle)
+                    .chain_err(|| "failed to bind TLS listener")?;
+
+            println!("Listening for TLS connections on {}", tls_config.bind_address);
+
+            let reload = acceptor.reload_on_sighup(&handle);
+            let server = handler.clone().listen(tls_listener, Some(acceptor));
+            Either::A(server.join(reload).map(|_| ()))
+        }
+        None => Either::B(future::empty()),
+    };
+
+    let http_websocket_server = handler.listen(listener, None).join(tls_server);
+
+    println!("Listening on {}", bind_address);
+
+    core.run(http_websocket_server.join4(petronel_worker, heartbeat, cache_flush))
+        .chain_err(|| "stream failed")?;
+
+    Ok(())
+}
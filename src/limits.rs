@@ -0,0 +1,203 @@
+use config;
+use petronel::error::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub struct LimitsConfig {
+    // Maximum concurrent websocket connections per client IP (0 = unlimited)
+    pub max_connections_per_ip: usize,
+    // Sustained websocket requests per second per connection (0 = unlimited)
+    pub request_rate: f64,
+    // Number of requests a connection can send in a burst
+    pub request_burst: f64,
+    // Largest websocket frame a client may send, in bytes
+    pub max_request_frame_size: usize,
+    pub output: OutputLimits,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_connections_per_ip: 0,
+            request_rate: 0.0,
+            request_burst: 4.0,
+            max_request_frame_size: 4096,
+            output: OutputLimits::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy {
+    // Stop sending tweets until the client catches up
+    Drop,
+    // Close the connection with code 1008
+    Disconnect,
+}
+
+// Limits on data queued for a websocket client that isn't reading fast enough
+#[derive(Clone, Copy)]
+pub struct OutputLimits {
+    pub max_bytes: usize,
+    pub max_frames: usize,
+    pub policy: SlowConsumerPolicy,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        OutputLimits {
+            max_bytes: 1 << 20,
+            max_frames: 1000,
+            policy: SlowConsumerPolicy::Drop,
+        }
+    }
+}
+
+impl OutputLimits {
+    // MAX_OUTPUT_BUFFER_BYTES: bytes queued per connection before the policy applies
+    // MAX_OUTPUT_BUFFER_FRAMES: same, but counting websocket frames
+    // SLOW_CONSUMER_POLICY: `drop` (default) or `disconnect`
+    fn from_env() -> Result<Self> {
+        let policy = match config::env_opt("SLOW_CONSUMER_POLICY").as_ref().map(String::as_str) {
+            None | Some("drop") => SlowConsumerPolicy::Drop,
+            Some("disconnect") => SlowConsumerPolicy::Disconnect,
+            Some(other) => bail!(
+                "invalid value for SLOW_CONSUMER_POLICY environment variable: {}",
+                other
+            ),
+        };
+
+        let default = Self::default();
+        Ok(OutputLimits {
+            max_bytes: config::env_parse("MAX_OUTPUT_BUFFER_BYTES", default.max_bytes)?,
+            max_frames: config::env_parse("MAX_OUTPUT_BUFFER_FRAMES", default.max_frames)?,
+            policy,
+        })
+    }
+
+    pub(crate) fn is_exceeded(&self, bytes: usize, frames: usize) -> bool {
+        bytes > self.max_bytes || frames > self.max_frames
+    }
+}
+
+impl LimitsConfig {
+    pub(crate) fn from_env() -> Result<Self> {
+        let default = Self::default();
+        let request_rate = config::env_parse("REQUEST_RATE_LIMIT", default.request_rate)?;
+
+        Ok(LimitsConfig {
+            max_connections_per_ip: config::env_parse(
+                "MAX_CONNECTIONS_PER_IP",
+                default.max_connections_per_ip,
+            )?,
+            request_rate,
+            request_burst: config::env_parse("REQUEST_BURST_LIMIT", request_rate.max(1.0) * 4.0)?,
+            max_request_frame_size: config::env_parse(
+                "MAX_REQUEST_FRAME_SIZE",
+                default.max_request_frame_size,
+            )?,
+            output: OutputLimits::from_env()?,
+        })
+    }
+
+    pub(crate) fn token_bucket(&self) -> Option<TokenBucket> {
+        if self.request_rate > 0.0 {
+            Some(TokenBucket::new(self.request_rate, self.request_burst))
+        } else {
+            None
+        }
+    }
+}
+
+// Keeps track of the number of open connections per IP address
+#[derive(Clone)]
+pub(crate) struct ConnectionLimiter {
+    max_per_ip: usize,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(max_per_ip: usize) -> Self {
+        ConnectionLimiter {
+            max_per_ip,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Returns a guard that holds the connection slot until it's dropped,
+    // or `None` if the IP address is already at its limit
+    pub(crate) fn acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_insert(0);
+
+        if self.max_per_ip > 0 && *count >= self.max_per_ip {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ConnectionGuard {
+            ip,
+            connections: self.connections.clone(),
+        })
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    ip: IpAddr,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+
+        let remaining = match connections.get_mut(&self.ip) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+
+        if remaining == 0 {
+            connections.remove(&self.ip);
+        }
+    }
+}
+
+pub(crate) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: f64, capacity: f64) -> Self {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub(crate) fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+
+        self.tokens = (self.tokens + elapsed_secs * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
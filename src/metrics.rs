@@ -0,0 +1,49 @@
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counters for things that happen outside of petronel, reported alongside
+// petronel's own metrics in `/api/metrics.json`
+#[derive(Default)]
+pub(crate) struct ServerMetrics {
+    pub(crate) connections_rejected: AtomicUsize,
+    pub(crate) requests_rate_limited: AtomicUsize,
+    pub(crate) origins_rejected: AtomicUsize,
+    pub(crate) requests_too_large: AtomicUsize,
+    pub(crate) tweets_dropped: AtomicUsize,
+    pub(crate) slow_consumers_disconnected: AtomicUsize,
+    pub(crate) ping_timeouts: AtomicUsize,
+    // Connections that failed or were dropped because of an I/O error
+    pub(crate) connection_errors: AtomicUsize,
+}
+
+impl ServerMetrics {
+    pub(crate) fn incr(counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+
+        json!({
+            "connectionsRejected": get(&self.connections_rejected),
+            "requestsRateLimited": get(&self.requests_rate_limited),
+            "originsRejected": get(&self.origins_rejected),
+            "requestsTooLarge": get(&self.requests_too_large),
+            "tweetsDropped": get(&self.tweets_dropped),
+            "slowConsumersDisconnected": get(&self.slow_consumers_disconnected),
+            "pingTimeouts": get(&self.ping_timeouts),
+            "connectionErrors": get(&self.connection_errors),
+        })
+    }
+
+    // Adds our counters to the JSON object exported by petronel
+    pub(crate) fn merge_into(&self, petronel_metrics: &[u8]) -> Vec<u8> {
+        match ::serde_json::from_slice::<Value>(petronel_metrics) {
+            Ok(Value::Object(mut map)) => {
+                map.insert("server".to_string(), self.to_json());
+                ::serde_json::to_vec(&map).unwrap()
+            }
+            _ => petronel_metrics.to_vec(),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use config;
+use std::str;
+use tk_http::server::Head;
+
+// A list of allowed `Origin` values, where `*` matches any sequence of
+// characters (e.g. `https://*.example.com`)
+pub struct OriginList {
+    patterns: Vec<String>,
+}
+
+impl OriginList {
+    pub fn new<I>(patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        OriginList {
+            patterns: patterns.into_iter().map(|p| p.as_ref().to_lowercase()).collect(),
+        }
+    }
+
+    // Returns `None` if the environment variable isn't set
+    pub(crate) fn from_env(name: &str) -> Option<Self> {
+        let patterns = config::env_list(name);
+
+        if patterns.is_empty() {
+            None
+        } else {
+            Some(OriginList::new(patterns))
+        }
+    }
+
+    pub(crate) fn allows(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+
+        self.patterns
+            .iter()
+            .any(|pattern| wildcard_match(pattern.as_bytes(), origin.as_bytes()))
+    }
+}
+
+pub(crate) fn origin_header(headers: &Head) -> Option<String> {
+    headers
+        .headers()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("Origin"))
+        .and_then(|(_, value)| str::from_utf8(value).ok())
+        .map(String::from)
+}
+
+fn wildcard_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // Position of the last `*` in the pattern, and the input position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == s[i] {
+            p += 1;
+            i += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` consume one more character and try again
+            p = star + 1;
+            i = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
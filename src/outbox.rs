@@ -0,0 +1,137 @@
+use bytes::Bytes;
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tk_bufstream::WriteBuf;
+use tokio_io::AsyncWrite;
+use websocket;
+
+// Queued frames are only copied into the write buffer once it has drained
+// below this size, so a stalled client holds references to shared frames
+// rather than its own copy of every message.
+const WRITE_CHUNK_SIZE: usize = 16 * 1024;
+
+struct Counters {
+    bytes: AtomicUsize,
+    frames: AtomicUsize,
+    closed: AtomicBool,
+}
+
+// The sending half of a connection's outbound queue. Cloning it is cheap,
+// and frames are shared between every connection they're sent to.
+#[derive(Clone)]
+pub struct Outbox {
+    sender: mpsc::UnboundedSender<(Bytes, usize)>,
+    counters: Arc<Counters>,
+}
+
+impl Outbox {
+    pub(crate) fn new<S>(write_buf: WriteBuf<S>) -> (Self, OutboxWriter<S>) {
+        let (outbox, receiver) = Self::detached();
+
+        let writer = OutboxWriter {
+            write_buf,
+            receiver,
+            counters: outbox.counters.clone(),
+            receiver_done: false,
+        };
+
+        (outbox, writer)
+    }
+
+    // An outbox that isn't connected to a socket, with the queued frames
+    // available from the returned stream instead (e.g., for benchmarks)
+    pub fn detached() -> (Self, mpsc::UnboundedReceiver<(Bytes, usize)>) {
+        let (sender, receiver) = mpsc::unbounded();
+        let counters = Arc::new(Counters {
+            bytes: AtomicUsize::new(0),
+            frames: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        });
+
+        (Outbox { sender, counters }, receiver)
+    }
+
+    // Bytes and frames that haven't been handed to the write buffer yet
+    pub(crate) fn queued(&self) -> (usize, usize) {
+        (
+            self.counters.bytes.load(Ordering::Relaxed),
+            self.counters.frames.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.counters.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn push(&self, bytes: Bytes, frames: usize) -> Result<(), ()> {
+        if self.is_closed() {
+            return Err(());
+        }
+
+        self.enqueue(bytes, frames)
+    }
+
+    // Queues a close frame. Nothing else is queued after it.
+    pub(crate) fn close(&self, code: u16, reason: &[u8]) {
+        if !self.counters.closed.swap(true, Ordering::Relaxed) {
+            let _ = self.enqueue(websocket::close_frame(code, reason), 1);
+        }
+    }
+
+    fn enqueue(&self, bytes: Bytes, frames: usize) -> Result<(), ()> {
+        self.counters.bytes.fetch_add(bytes.len(), Ordering::Relaxed);
+        self.counters.frames.fetch_add(frames, Ordering::Relaxed);
+        self.sender.unbounded_send((bytes, frames)).map_err(|_| ())
+    }
+}
+
+// Drains a connection's outbound queue into its socket. Finishes once every
+// `Outbox` for the connection is gone and everything has been written.
+pub(crate) struct OutboxWriter<S> {
+    write_buf: WriteBuf<S>,
+    receiver: mpsc::UnboundedReceiver<(Bytes, usize)>,
+    counters: Arc<Counters>,
+    receiver_done: bool,
+}
+
+impl<S> Future for OutboxWriter<S>
+where
+    S: AsyncWrite,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            while !self.receiver_done && self.write_buf.out_buf.len() < WRITE_CHUNK_SIZE {
+                match self.receiver.poll()? {
+                    Async::Ready(Some((bytes, frames))) => {
+                        self.write_buf.out_buf.extend(&bytes);
+                        self.counters.bytes.fetch_sub(bytes.len(), Ordering::Relaxed);
+                        self.counters.frames.fetch_sub(frames, Ordering::Relaxed);
+                    }
+                    Async::Ready(None) => self.receiver_done = true,
+                    Async::NotReady => break,
+                }
+            }
+
+            let len_before = self.write_buf.out_buf.len();
+            if len_before == 0 {
+                return if self.receiver_done {
+                    Ok(Async::Ready(()))
+                } else {
+                    Ok(Async::NotReady)
+                };
+            }
+
+            self.write_buf.flush().map_err(|_| ())?;
+
+            // The socket isn't ready for more, and will wake us up when it is
+            if self.write_buf.out_buf.len() == len_before {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
@@ -1,7 +1,13 @@
 use bytes::Bytes;
+use directory::{self, BossDirectory};
+use filter::FilterKey;
+use frame_cache::FrameCache;
 use petronel;
 use petronel::model::Message as PetronelMessage;
-use protobuf;
+use prost::Message;
+use protobuf::{self, ResponseMessage};
+use std::mem;
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 use websocket;
 
@@ -37,7 +43,7 @@ fn language_to_proto(language: petronel::model::Language) -> i32 {
     }) as i32
 }
 
-fn boss_to_proto(boss: &petronel::model::RaidBoss) -> protobuf::RaidBoss {
+pub fn boss_to_proto(boss: &petronel::model::RaidBoss) -> protobuf::RaidBoss {
     protobuf::RaidBoss {
         name: boss.name.to_string(),
         image: boss.image.clone().map(|i| i.to_string()),
@@ -48,7 +54,7 @@ fn boss_to_proto(boss: &petronel::model::RaidBoss) -> protobuf::RaidBoss {
     }
 }
 
-fn tweet_to_proto(tweet: &petronel::model::RaidTweet) -> protobuf::RaidTweetResponse {
+pub fn tweet_to_proto(tweet: &petronel::model::RaidTweet) -> protobuf::RaidTweetResponse {
     protobuf::RaidTweetResponse {
         boss_name: tweet.boss_name.to_string(),
         raid_id: tweet.raid_id.to_string(),
@@ -69,45 +75,300 @@ fn tweet_to_proto(tweet: &petronel::model::RaidTweet) -> protobuf::RaidTweetResp
     }
 }
 
-pub(crate) fn petronel_message_to_bytes(msg: PetronelMessage) -> Option<Bytes> {
-    use self::PetronelMessage::*;
-    use protobuf::ResponseMessage;
-    use protobuf::response_message::Data::*;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MessageKind {
+    Tweet,
+    Boss,
+    KeepAlive,
+    // Sent directly in reply to a request
+    Reply,
+}
+
+// The raid a tweet is for
+pub(crate) struct RaidKey {
+    pub(crate) raid_id: String,
+    pub(crate) boss_name: String,
+}
+
+// One or more serialized websocket frames, ready to be sent to subscribers
+pub(crate) struct EncodedMessage {
+    pub(crate) kind: MessageKind,
+    pub(crate) frames: Vec<Bytes>,
+    // Set on broadcasts that connections can filter out. Replies to a
+    // connection's own requests are always sent.
+    pub(crate) filter_key: Option<FilterKey>,
+    // The boss a boss update is for, so connections can follow new translations
+    pub(crate) updated_boss: Option<petronel::model::RaidBoss>,
+    // Set on broadcast tweets, for connections that drop duplicate raids
+    pub(crate) raid: Option<RaidKey>,
+}
+
+impl EncodedMessage {
+    pub(crate) fn len(&self) -> usize {
+        self.frames.iter().map(Bytes::len).sum()
+    }
+}
+
+// Turns petronel messages into websocket frames. Tweets are encoded once when
+// they arrive, and the same frames are reused for tweet history requests.
+//
+// Boss changes are also recorded in the directory as they pass through.
+pub(crate) struct MessageEncoder {
+    tweet_frames: Mutex<FrameCache>,
+    directory: BossDirectory,
+    boss_list_chunk_size: usize,
+}
+
+impl MessageEncoder {
+    pub(crate) fn new(
+        tweet_cache_size: usize,
+        directory: BossDirectory,
+        boss_list_chunk_size: usize,
+    ) -> Self {
+        MessageEncoder {
+            tweet_frames: Mutex::new(FrameCache::new(tweet_cache_size)),
+            directory,
+            boss_list_chunk_size,
+        }
+    }
+
+    pub(crate) fn encode(&self, msg: PetronelMessage) -> Option<EncodedMessage> {
+        use self::PetronelMessage::*;
+        use protobuf::response_message::Data::*;
 
-    let data = match msg {
-        Heartbeat => Some(KeepAliveMessage(protobuf::KeepAliveResponse {})),
-        Tweet(tweet) => Some(RaidTweetMessage(tweet_to_proto(tweet))),
-        TweetList(tweets) => {
-            // TODO: This is pretty inefficient
-            let mut total_len = 0;
-            let mut frames = Vec::with_capacity(tweets.len());
+        let mut updated_boss = None;
 
-            for tweet in tweets {
-                let message = RaidTweetMessage(tweet_to_proto(tweet));
-                let response = ResponseMessage {
-                    data: Some(message),
+        let (kind, data, filter_key) = match msg {
+            Heartbeat => (
+                MessageKind::KeepAlive,
+                KeepAliveMessage(protobuf::KeepAliveResponse {}),
+                None,
+            ),
+            Tweet(tweet) => {
+                let filter_key = FilterKey {
+                    language: tweet.language,
+                    level: self.directory.level(&tweet.boss_name),
                 };
-                if let Some(bytes) = websocket::serialize_protobuf(response) {
-                    total_len += bytes.len();
-                    frames.push(bytes);
+                let mut cache = self.tweet_frames.lock().unwrap();
+
+                return tweet_frame(&mut cache, tweet).map(|frame| EncodedMessage {
+                    kind: MessageKind::Tweet,
+                    frames: vec![frame],
+                    filter_key: Some(filter_key),
+                    updated_boss: None,
+                    raid: Some(RaidKey {
+                        raid_id: tweet.raid_id.to_string(),
+                        boss_name: tweet.boss_name.to_string(),
+                    }),
+                });
+            }
+            TweetList(tweets) => {
+                let mut cache = self.tweet_frames.lock().unwrap();
+                let frames = tweets
+                    .into_iter()
+                    .filter_map(|tweet| tweet_frame(&mut cache, tweet))
+                    .collect::<Vec<_>>();
+
+                if frames.is_empty() {
+                    return None;
                 }
+
+                // Tweet history is only sent in reply to a request, so it's
+                // never dropped for a slow client
+                return Some(EncodedMessage {
+                    kind: MessageKind::Reply,
+                    frames,
+                    filter_key: None,
+                    updated_boss: None,
+                    raid: None,
+                });
             }
+            BossUpdate(boss) => {
+                self.directory.update(boss);
+                updated_boss = Some(boss.clone());
 
-            let mut bytes = Bytes::with_capacity(total_len);
-            for frame in frames {
-                bytes.extend(frame);
+                (
+                    MessageKind::Boss,
+                    RaidBossesMessage(protobuf::RaidBossesResponse {
+                        raid_bosses: vec![boss_to_proto(boss)],
+                        ..Default::default()
+                    }),
+                    Some(FilterKey {
+                        language: boss.language,
+                        level: Some(boss.level),
+                    }),
+                )
+            }
+            BossList(bosses) => {
+                self.directory.sync(bosses.iter().cloned());
+
+                return Some(boss_list_message(
+                    bosses.iter().cloned(),
+                    &[],
+                    directory::now_as_milliseconds(),
+                    self.boss_list_chunk_size,
+                    String::new(),
+                ));
+            }
+            BossRemove(boss_name) => {
+                self.directory.remove(&boss_name);
+                return None;
             }
+        };
+
+        let message = ResponseMessage {
+            data: Some(data),
+            ..Default::default()
+        };
+
+        websocket::serialize_protobuf(message).map(|frame| {
+            EncodedMessage {
+                kind,
+                frames: vec![frame],
+                filter_key,
+                updated_boss,
+                raid: None,
+            }
+        })
+    }
+}
+
+// Replies to a boss list request, split into responses of about
+// `chunk_size` bytes at most so that no single frame gets too large.
+// Every chunk carries the request ID. The timestamp must be taken before
+// reading the bosses, so that nothing changed in between is missed.
+pub(crate) fn boss_list_message<'a, I>(
+    bosses: I,
+    removed: &[petronel::model::BossName],
+    sync_timestamp: i64,
+    chunk_size: usize,
+    request_id: String,
+) -> EncodedMessage
+where
+    I: IntoIterator<Item = &'a petronel::model::RaidBoss>,
+{
+    use protobuf::response_message::Data::RaidBossesMessage;
 
-            return Some(bytes);
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_len = 0;
+
+    for boss in bosses {
+        let boss = boss_to_proto(boss);
+        // Allow a few bytes for the field tag and length prefix
+        let len = boss.encoded_len() + 4;
+
+        if !chunk.is_empty() && chunk_len + len > chunk_size {
+            chunks.push(mem::replace(&mut chunk, Vec::new()));
+            chunk_len = 0;
         }
-        BossUpdate(boss) => Some(RaidBossesMessage(protobuf::RaidBossesResponse {
-            raid_bosses: vec![boss_to_proto(boss)],
-        })),
-        BossList(bosses) => Some(RaidBossesMessage(protobuf::RaidBossesResponse {
-            raid_bosses: bosses.iter().cloned().map(boss_to_proto).collect(),
-        })),
-        BossRemove(_boss_name) => None,
+
+        chunk_len += len;
+        chunk.push(boss);
+    }
+    chunks.push(chunk);
+
+    let last_index = chunks.len() - 1;
+
+    let frames = chunks
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, raid_bosses)| {
+            let removed_boss_names = if i == 0 {
+                removed.iter().map(|name| name.to_string()).collect()
+            } else {
+                vec![]
+            };
+
+            let response = protobuf::RaidBossesResponse {
+                raid_bosses,
+                sync_timestamp,
+                partial: i < last_index,
+                removed_boss_names,
+            };
+
+            websocket::serialize_protobuf(ResponseMessage {
+                data: Some(RaidBossesMessage(response)),
+                request_id: request_id.clone(),
+            })
+        })
+        .collect();
+
+    EncodedMessage {
+        kind: MessageKind::Boss,
+        frames,
+        filter_key: None,
+        updated_boss: None,
+        raid: None,
+    }
+}
+
+// Sent in place of a tweet for a raid the connection has already been sent
+pub(crate) fn raid_duplicate_message(raid: &RaidKey, count: u32) -> Option<EncodedMessage> {
+    use protobuf::response_message::Data::RaidDuplicateMessage;
+
+    let response = protobuf::RaidDuplicateResponse {
+        raid_id: raid.raid_id.clone(),
+        boss_name: raid.boss_name.clone(),
+        count: count as i32,
     };
 
-    data.and_then(|d| websocket::serialize_protobuf(ResponseMessage { data: Some(d) }))
+    websocket::serialize_protobuf(ResponseMessage {
+        data: Some(RaidDuplicateMessage(response)),
+        ..Default::default()
+    }).map(|frame| EncodedMessage {
+        kind: MessageKind::Tweet,
+        frames: vec![frame],
+        filter_key: None,
+        updated_boss: None,
+        raid: None,
+    })
+}
+
+pub(crate) fn ack_message(request_id: String) -> Option<EncodedMessage> {
+    use protobuf::response_message::Data::AckMessage;
+
+    reply_message(AckMessage(protobuf::AckResponse {}), request_id)
+}
+
+pub(crate) fn error_message(
+    code: protobuf::error_response::Code,
+    message: String,
+    request_id: String,
+) -> Option<EncodedMessage> {
+    use protobuf::response_message::Data::ErrorMessage;
+
+    let response = protobuf::ErrorResponse {
+        code: code as i32,
+        message,
+    };
+
+    reply_message(ErrorMessage(response), request_id)
+}
+
+pub(crate) fn reply_message(
+    data: protobuf::response_message::Data,
+    request_id: String,
+) -> Option<EncodedMessage> {
+    websocket::serialize_protobuf(ResponseMessage {
+        data: Some(data),
+        request_id,
+    }).map(|frame| EncodedMessage {
+        kind: MessageKind::Reply,
+        frames: vec![frame],
+        filter_key: None,
+        updated_boss: None,
+        raid: None,
+    })
+}
+
+fn tweet_frame(cache: &mut FrameCache, tweet: &petronel::model::RaidTweet) -> Option<Bytes> {
+    use protobuf::response_message::Data::RaidTweetMessage;
+
+    cache.get_or_insert_with(tweet.tweet_id, || {
+        websocket::serialize_protobuf(ResponseMessage {
+            data: Some(RaidTweetMessage(tweet_to_proto(tweet))),
+            ..Default::default()
+        })
+    })
 }
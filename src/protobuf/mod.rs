@@ -1,4 +1,8 @@
-pub(crate) mod convert;
+// Public with the `bench` feature, for the benchmarks
+#[cfg(feature = "bench")]
+pub mod convert;
+#[cfg(not(feature = "bench"))]
+mod convert;
 
 include!(concat!(
     env!("OUT_DIR"),
@@ -0,0 +1,343 @@
+use byteorder::{BigEndian, ByteOrder};
+use config;
+use futures::{future, Async, Future, Poll};
+use petronel::error::*;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+use std::time::Duration;
+use tk_http::server::Head;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::AsyncRead;
+
+const PROXY_V1_PREFIX: &[u8] = b"PROXY ";
+const PROXY_V1_MIN_LEN: usize = 15; // "PROXY UNKNOWN\r\n"
+const PROXY_V1_MAX_LEN: usize = 107;
+const PROXY_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const PROXY_V2_HEADER_LEN: usize = 16;
+const PROXY_HEADER_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyMode {
+    // Use the address of the TCP peer
+    None,
+    // Read `Forwarded` or `X-Forwarded-For` headers sent by trusted proxies
+    Headers,
+    // Read a PROXY protocol (v1 or v2) header from trusted proxies on accept
+    ProxyProtocol,
+}
+
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    // Required unless the mode is `None`
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            mode: ProxyMode::None,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl ProxyConfig {
+    // TRUSTED_PROXY_MODE: one of `none` (default), `headers` or `proxy-protocol`
+    // TRUSTED_PROXIES: comma-separated IP addresses of proxies to trust
+    pub(crate) fn from_env() -> Result<Self> {
+        let mode = match config::env_opt("TRUSTED_PROXY_MODE").as_ref().map(String::as_str) {
+            None | Some("none") => ProxyMode::None,
+            Some("headers") => ProxyMode::Headers,
+            Some("proxy-protocol") => ProxyMode::ProxyProtocol,
+            Some(other) => bail!(
+                "invalid value for TRUSTED_PROXY_MODE environment variable: {}",
+                other
+            ),
+        };
+
+        let trusted_proxies = config::env_list("TRUSTED_PROXIES")
+            .iter()
+            .map(|ip| ip.parse())
+            .collect::<::std::result::Result<Vec<IpAddr>, _>>()
+            .chain_err(|| "invalid value for TRUSTED_PROXIES environment variable")?;
+
+        // Otherwise any client could claim to be a proxy and pick its own address
+        if mode != ProxyMode::None && trusted_proxies.is_empty() {
+            bail!("TRUSTED_PROXIES must be set when TRUSTED_PROXY_MODE is enabled");
+        }
+
+        Ok(ProxyConfig {
+            mode,
+            trusted_proxies,
+        })
+    }
+
+    // Resolves the client address of a newly accepted connection, reading the
+    // PROXY protocol header first if the peer is a trusted proxy
+    pub(crate) fn accept<S>(
+        &self,
+        socket: S,
+        peer_addr: SocketAddr,
+        handle: &Handle,
+    ) -> Box<Future<Item = (S, SocketAddr), Error = io::Error>>
+    where
+        S: AsyncRead + 'static,
+    {
+        if self.mode != ProxyMode::ProxyProtocol || !self.is_trusted(peer_addr.ip()) {
+            return Box::new(future::ok((socket, peer_addr)));
+        }
+
+        let header = read_proxy_header(socket)
+            .map(move |(socket, addr)| (socket, addr.unwrap_or(peer_addr)));
+
+        let timeout = Duration::new(PROXY_HEADER_TIMEOUT_SECONDS, 0);
+        let timeout = future::result(Timeout::new(timeout, handle))
+            .flatten()
+            .and_then(|()| -> io::Result<(S, SocketAddr)> {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for PROXY header",
+                ))
+            });
+
+        Box::new(header.select(timeout).map(|(r, _)| r).map_err(|(e, _)| e))
+    }
+
+    pub(crate) fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(&ip)
+    }
+
+    // Determines the client IP for an HTTP request. If the peer is a trusted
+    // proxy, the closest untrusted address in the forwarding chain is used.
+    // Entries further left are set by the client, so they're never used.
+    pub(crate) fn client_ip(&self, headers: &Head, peer_ip: IpAddr) -> IpAddr {
+        if self.mode != ProxyMode::Headers || !self.is_trusted(peer_ip) {
+            return peer_ip;
+        }
+
+        let mut forwarded = Vec::new();
+        let mut x_forwarded_for = Vec::new();
+
+        for (name, value) in headers.headers() {
+            let value = match str::from_utf8(value) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            if name.eq_ignore_ascii_case("Forwarded") {
+                forwarded.extend(parse_forwarded(value));
+            } else if name.eq_ignore_ascii_case("X-Forwarded-For") {
+                x_forwarded_for.extend(value.split(',').filter_map(parse_ip));
+            }
+        }
+
+        let chain = if forwarded.is_empty() {
+            x_forwarded_for
+        } else {
+            forwarded
+        };
+
+        chain
+            .iter()
+            .rev()
+            .cloned()
+            .find(|&ip| !self.is_trusted(ip))
+            .unwrap_or(peer_ip)
+    }
+}
+
+// Extracts the `for=` addresses from a `Forwarded` header (RFC 7239)
+fn parse_forwarded(value: &str) -> Vec<IpAddr> {
+    value
+        .split(',')
+        .flat_map(|element| element.split(';'))
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case("for") => {
+                    parse_ip(value.trim().trim_matches('"'))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Accepts addresses with or without ports, e.g. `1.2.3.4`, `1.2.3.4:80`,
+// `2001:db8::1` or `[2001:db8::1]:80`
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+
+    if let Ok(ip) = s.parse() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    if s.starts_with('[') {
+        return s[1..].split(']').next().and_then(|ip| ip.parse().ok());
+    }
+
+    None
+}
+
+// Reads a PROXY protocol header from the start of a connection, returning
+// the socket and the source address advertised by the proxy (if any).
+// Bytes are only read up to the end of the header, so that the rest of the
+// stream can be handed off to the HTTP server untouched.
+pub(crate) fn read_proxy_header<S>(socket: S) -> ReadProxyHeader<S>
+where
+    S: AsyncRead,
+{
+    ReadProxyHeader {
+        socket: Some(socket),
+        buf: Vec::with_capacity(PROXY_V1_MAX_LEN),
+    }
+}
+
+pub(crate) struct ReadProxyHeader<S> {
+    socket: Option<S>,
+    buf: Vec<u8>,
+}
+
+impl<S> Future for ReadProxyHeader<S>
+where
+    S: AsyncRead,
+{
+    type Item = (S, Option<SocketAddr>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let needed = bytes_needed(&self.buf)?;
+
+            if needed == 0 {
+                let addr = parse_header(&self.buf)?;
+                let socket = self.socket.take().expect("polled ReadProxyHeader after completion");
+                return Ok(Async::Ready((socket, addr)));
+            }
+
+            let start = self.buf.len();
+            self.buf.resize(start + needed, 0);
+
+            let result = self.socket
+                .as_mut()
+                .expect("polled ReadProxyHeader after completion")
+                .read(&mut self.buf[start..]);
+
+            match result {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before PROXY header was received",
+                    ))
+                }
+                Ok(n) => self.buf.truncate(start + n),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.buf.truncate(start);
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Number of bytes that can be safely read without reading past the header
+fn bytes_needed(buf: &[u8]) -> io::Result<usize> {
+    // Both versions are at least this long
+    if buf.len() < PROXY_V1_MIN_LEN {
+        return Ok(PROXY_V1_MIN_LEN - buf.len());
+    }
+
+    if buf.starts_with(PROXY_V2_SIGNATURE) {
+        if buf.len() < PROXY_V2_HEADER_LEN {
+            return Ok(PROXY_V2_HEADER_LEN - buf.len());
+        }
+
+        let total = PROXY_V2_HEADER_LEN + BigEndian::read_u16(&buf[14..16]) as usize;
+        Ok(total - buf.len())
+    } else if buf.starts_with(PROXY_V1_PREFIX) {
+        if buf.ends_with(b"\r\n") {
+            Ok(0)
+        } else if buf.len() >= PROXY_V1_MAX_LEN {
+            Err(invalid("PROXY v1 header too long"))
+        } else {
+            // The header is terminated by CRLF, so read one byte at a time
+            Ok(1)
+        }
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+fn parse_header(buf: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if buf.starts_with(PROXY_V2_SIGNATURE) {
+        parse_v2(buf)
+    } else {
+        parse_v1(buf)
+    }
+}
+
+// e.g., `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`
+fn parse_v1(buf: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = str::from_utf8(&buf[..buf.len() - 2])
+        .map_err(|_| invalid("PROXY v1 header is not valid UTF-8"))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+
+    match parts.get(1).cloned() {
+        Some("TCP4") | Some("TCP6") if parts.len() == 6 => {
+            let ip = parts[2]
+                .parse::<IpAddr>()
+                .map_err(|_| invalid("invalid source address in PROXY v1 header"))?;
+            let port = parts[4]
+                .parse::<u16>()
+                .map_err(|_| invalid("invalid source port in PROXY v1 header"))?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        Some("UNKNOWN") => Ok(None),
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0F;
+    let family = buf[13];
+    let addresses = &buf[PROXY_V2_HEADER_LEN..];
+
+    if version != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    // LOCAL command, used by the proxy for health checks
+    if command == 0 {
+        return Ok(None);
+    }
+
+    match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = BigEndian::read_u16(&addresses[8..10]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = BigEndian::read_u16(&addresses[32..34]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,129 @@
+use api_cache::ResponseCache;
+use assets::StaticAssets;
+use codec;
+use config::Config;
+use directory::BossDirectory;
+use futures::{future, Future, Stream};
+use limits::ConnectionLimiter;
+use metrics::ServerMetrics;
+use petronel;
+use petronel::error::*;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use stream::ClientStream;
+use tk_http::server::{Config as HttpConfig, Proto};
+use tk_listen::ListenExt;
+use tls::TlsAcceptor;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+
+const MAX_CONNECTIONS: usize = 1000;
+
+pub(crate) type PetronelClient = petronel::Client<codec::WebsocketSubscriber, Vec<u8>>;
+
+// Everything needed to serve HTTP and websocket requests on an accepted
+// connection, shared between the plain TCP and TLS listeners
+#[derive(Clone)]
+pub(crate) struct ConnectionHandler {
+    pub(crate) handle: Handle,
+    pub(crate) state: HandlerState,
+}
+
+// The parts of a `ConnectionHandler` that aren't tied to a reactor
+#[derive(Clone)]
+pub(crate) struct HandlerState {
+    pub(crate) http_config: Arc<HttpConfig>,
+    pub(crate) petronel_client: PetronelClient,
+    pub(crate) config: Arc<Config>,
+    pub(crate) metrics: Arc<ServerMetrics>,
+    pub(crate) connection_limiter: ConnectionLimiter,
+    pub(crate) bosses_cache: ResponseCache,
+    pub(crate) assets: Option<Arc<StaticAssets>>,
+    pub(crate) directory: BossDirectory,
+}
+
+impl ConnectionHandler {
+    pub(crate) fn listen(
+        self,
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let sockets = listener
+            .incoming()
+            .sleep_on_error(Duration::from_millis(1000), &self.handle)
+            .map(move |(socket, peer_addr)| (socket, peer_addr, tls.clone()));
+
+        self.serve_all(sockets)
+    }
+
+    fn serve_all<I>(self, sockets: I) -> Box<Future<Item = (), Error = Error>>
+    where
+        I: Stream<Item = (TcpStream, SocketAddr, Option<TlsAcceptor>), Error = ()> + 'static,
+    {
+        let server = sockets
+            .map(move |(socket, peer_addr, tls)| {
+                let handler = self.clone();
+                let metrics = self.state.metrics.clone();
+
+                self.accept(socket, peer_addr, tls)
+                    .map_err(move |_| ServerMetrics::incr(&metrics.connection_errors))
+                    .and_then(move |(stream, client_addr)| handler.serve(stream, client_addr))
+                    .then(|_| Ok(()))
+            })
+            .listen(MAX_CONNECTIONS)
+            .map_err(|()| Error::from_kind(ErrorKind::Msg("HTTP/websocket server failed".into())));
+
+        Box::new(server)
+    }
+
+    // Reads the PROXY protocol header and performs the TLS handshake, if enabled
+    fn accept(
+        &self,
+        socket: TcpStream,
+        peer_addr: SocketAddr,
+        tls: Option<TlsAcceptor>,
+    ) -> Box<Future<Item = (ClientStream, SocketAddr), Error = io::Error>> {
+        let handle = self.handle.clone();
+        let accepted = self.state
+            .config
+            .proxy
+            .accept(socket, peer_addr, &self.handle)
+            .and_then(move |(socket, client_addr)| match tls {
+                Some(acceptor) => future::Either::A(
+                    acceptor
+                        .accept(socket, &handle)
+                        .map(move |stream| (ClientStream::Tls(stream), client_addr)),
+                ),
+                None => future::Either::B(future::ok((ClientStream::Plain(socket), client_addr))),
+            });
+
+        Box::new(accepted)
+    }
+
+    fn serve(
+        &self,
+        stream: ClientStream,
+        client_addr: SocketAddr,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let state = &self.state;
+        let dispatcher = codec::RequestDispatcher {
+            handle: self.handle.clone(),
+            petronel_client: state.petronel_client.clone(),
+            config: state.config.clone(),
+            metrics: state.metrics.clone(),
+            connection_limiter: state.connection_limiter.clone(),
+            bosses_cache: state.bosses_cache.clone(),
+            assets: state.assets.clone(),
+            directory: state.directory.clone(),
+            peer_addr: client_addr,
+        };
+
+        let metrics = state.metrics.clone();
+        let proto = Proto::new(stream, &state.http_config, dispatcher, &self.handle)
+            .map_err(move |_| ServerMetrics::incr(&metrics.connection_errors));
+
+        Box::new(proto)
+    }
+}
@@ -0,0 +1,49 @@
+use futures::Poll;
+use rustls::ServerSession;
+use std::io::{self, Read, Write};
+use tokio_core::net::TcpStream;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsStream;
+
+// A client connection, accepted by either the plain TCP or the TLS listener.
+// Having a single type for both lets them share one petronel client.
+pub(crate) enum ClientStream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream, ServerSession>),
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.read(buf),
+            ClientStream::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.write(buf),
+            ClientStream::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ClientStream::Plain(ref mut s) => s.flush(),
+            ClientStream::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {}
+
+impl AsyncWrite for ClientStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            ClientStream::Plain(ref mut s) => AsyncWrite::shutdown(s),
+            ClientStream::Tls(ref mut s) => AsyncWrite::shutdown(s),
+        }
+    }
+}
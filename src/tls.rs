@@ -0,0 +1,193 @@
+use config;
+use futures::{future, Future, Stream};
+use petronel::error::*;
+use rustls::{NoClientAuth, ServerConfig, ServerSession};
+use rustls::internal::pemfile;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_rustls::{ServerConfigExt, TlsStream};
+use tokio_signal::unix::{Signal, SIGHUP};
+
+// Clients that haven't finished the handshake by then are disconnected, the
+// same as for PROXY headers
+const HANDSHAKE_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Clone)]
+pub(crate) struct TlsConfig {
+    pub(crate) bind_address: SocketAddr,
+    cert_path: String,
+    key_path: String,
+}
+
+impl TlsConfig {
+    // TLS_BIND_ADDRESS: address for the HTTPS/wss:// listener, disabled if unset
+    // TLS_CERT_PATH: PEM-encoded certificate chain
+    // TLS_KEY_PATH: PEM-encoded private key (PKCS#8 or RSA)
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let bind_address = match config::env_opt("TLS_BIND_ADDRESS") {
+            Some(address) => address
+                .parse()
+                .chain_err(|| "invalid value for TLS_BIND_ADDRESS environment variable")?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(TlsConfig {
+            bind_address,
+            cert_path: config::env("TLS_CERT_PATH")?,
+            key_path: config::env("TLS_KEY_PATH")?,
+        }))
+    }
+
+    fn load(&self) -> Result<Arc<ServerConfig>> {
+        let certs = pemfile::certs(&mut open(&self.cert_path)?)
+            .map_err(|()| format!("failed to parse TLS certificate {}", self.cert_path))?;
+
+        let mut keys = pemfile::pkcs8_private_keys(&mut open(&self.key_path)?)
+            .map_err(|()| format!("failed to parse TLS key {}", self.key_path))?;
+
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut open(&self.key_path)?)
+                .map_err(|()| format!("failed to parse TLS key {}", self.key_path))?;
+        }
+
+        if certs.is_empty() {
+            bail!("no certificates found in {}", self.cert_path);
+        }
+
+        if keys.is_empty() {
+            bail!("no private keys found in {}", self.key_path);
+        }
+
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.set_single_cert(certs, keys.remove(0));
+
+        Ok(Arc::new(server_config))
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .chain_err(|| format!("failed to open {}", path))
+}
+
+// Holds the current TLS configuration, which can be swapped out at runtime
+// when the certificate is renewed
+#[derive(Clone)]
+pub(crate) struct TlsAcceptor {
+    config: Arc<TlsConfig>,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(config: &TlsConfig) -> Result<Self> {
+        let server_config = config.load()?;
+
+        Ok(TlsAcceptor {
+            config: Arc::new(config.clone()),
+            server_config: Arc::new(RwLock::new(server_config)),
+        })
+    }
+
+    pub(crate) fn accept(
+        &self,
+        socket: TcpStream,
+        handle: &Handle,
+    ) -> Box<Future<Item = TlsStream<TcpStream, ServerSession>, Error = io::Error>> {
+        self.accept_within(socket, Duration::new(HANDSHAKE_TIMEOUT_SECONDS, 0), handle)
+    }
+
+    fn accept_within(
+        &self,
+        socket: TcpStream,
+        timeout: Duration,
+        handle: &Handle,
+    ) -> Box<Future<Item = TlsStream<TcpStream, ServerSession>, Error = io::Error>> {
+        let server_config = self.server_config.read().unwrap().clone();
+        let handshake = server_config.accept_async(socket);
+
+        let timeout = future::result(Timeout::new(timeout, handle))
+            .flatten()
+            .and_then(|()| -> io::Result<TlsStream<TcpStream, ServerSession>> {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for TLS handshake",
+                ))
+            });
+
+        Box::new(handshake.select(timeout).map(|(r, _)| r).map_err(|(e, _)| e))
+    }
+
+    fn reload(&self) -> Result<()> {
+        let server_config = self.config.load()?;
+        *self.server_config.write().unwrap() = server_config;
+        Ok(())
+    }
+
+    // Reloads the certificate and key whenever the process receives SIGHUP.
+    // If reloading fails, the previous certificate stays in use.
+    pub(crate) fn reload_on_sighup(
+        &self,
+        handle: &Handle,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let acceptor = self.clone();
+
+        let reloads = Signal::new(SIGHUP, handle)
+            .flatten_stream()
+            .for_each(move |_| {
+                match acceptor.reload() {
+                    Ok(()) => println!("Reloaded TLS certificate"),
+                    Err(e) => eprintln!("Failed to reload TLS certificate: {}", e),
+                }
+                Ok(())
+            })
+            .then(|r| r.chain_err(|| "failed to listen for SIGHUP"));
+
+        Box::new(reloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{self, TcpListener};
+    use std::time::Instant;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn stalled_handshakes_time_out() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        // The handshake never gets as far as needing a certificate
+        let acceptor = TlsAcceptor {
+            config: Arc::new(TlsConfig {
+                bind_address: "127.0.0.1:0".parse().unwrap(),
+                cert_path: String::new(),
+                key_path: String::new(),
+            }),
+            server_config: Arc::new(RwLock::new(Arc::new(ServerConfig::new(NoClientAuth::new())))),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Connects, then sends nothing
+        let _client = net::TcpStream::connect(addr).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        let socket = TcpStream::from_stream(socket, &handle).unwrap();
+
+        let started = Instant::now();
+        let handshake = acceptor.accept_within(socket, Duration::from_millis(100), &handle);
+        match core.run(handshake) {
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => panic!("expected a timeout, got {}", e),
+            Ok(_) => panic!("expected a timeout, but the handshake finished"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
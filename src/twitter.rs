@@ -0,0 +1,217 @@
+use futures::{Async, Future, Poll};
+use hyper::Uri;
+use hyper::client::HttpConnector;
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
+use petronel::error::*;
+use std::io::{self, Read, Write};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_service::Service;
+
+// Connector for the Twitter streaming API client. If a stream URL is
+// configured, every request is sent to that host instead of Twitter (e.g., a
+// fake streaming server in tests), with the URL's path in front of its own.
+pub(crate) struct StreamConnector {
+    https: HttpsConnector<HttpConnector>,
+    redirect: Option<Redirect>,
+}
+
+struct Redirect {
+    uri: Uri,
+    // Without a trailing slash, since request paths start with one
+    path_prefix: String,
+}
+
+impl StreamConnector {
+    pub(crate) fn new(stream_url: Option<&String>, handle: &Handle) -> Result<Self> {
+        let https = HttpsConnector::new(1, handle).chain_err(|| "HTTPS error")?;
+
+        let redirect = match stream_url {
+            Some(url) => {
+                let uri = url.parse::<Uri>()
+                    .chain_err(|| format!("invalid Twitter stream URL {}", url))?;
+
+                match uri.scheme() {
+                    Some("http") | Some("https") => {}
+                    _ => bail!("Twitter stream URL {} must start with http:// or https://", url),
+                }
+
+                let path_prefix = uri.path().trim_right_matches('/').to_string();
+                Some(Redirect { uri, path_prefix })
+            }
+            None => None,
+        };
+
+        Ok(StreamConnector { https, redirect })
+    }
+
+    // Connections carry one request each when redirected, since only the
+    // first request line on a connection gets the path prefix
+    pub(crate) fn keep_alive(&self) -> bool {
+        self.redirect.is_none()
+    }
+}
+
+impl Service for StreamConnector {
+    type Request = Uri;
+    type Response = StreamConnection;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Self::Response, Error = io::Error>>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        match self.redirect {
+            Some(ref redirect) => {
+                let path_prefix = redirect.path_prefix.clone();
+                let connection = self.https
+                    .call(redirect.uri.clone())
+                    .map(move |stream| StreamConnection::new(stream, path_prefix));
+                Box::new(connection)
+            }
+            None => Box::new(self.https.call(uri).map(|stream| {
+                StreamConnection::new(stream, String::new())
+            })),
+        }
+    }
+}
+
+// A connection made by `StreamConnector`, which adds the stream URL's path
+// to the request line of the first request written to it
+pub(crate) struct StreamConnection {
+    stream: MaybeHttpsStream<TcpStream>,
+    path_prefix: String,
+    // The request line so far, until it's complete
+    request_line: Option<Vec<u8>>,
+    // The rewritten request line, until it's written to the stream
+    pending: Vec<u8>,
+}
+
+impl StreamConnection {
+    fn new(stream: MaybeHttpsStream<TcpStream>, path_prefix: String) -> Self {
+        let request_line = if path_prefix.is_empty() {
+            None
+        } else {
+            Some(Vec::new())
+        };
+
+        StreamConnection {
+            stream,
+            path_prefix,
+            request_line,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending)? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                written => {
+                    self.pending.drain(..written);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Inserts the prefix in front of an origin-form request target, e.g.
+// "GET /1.1/statuses/filter.json HTTP/1.1"
+fn add_path_prefix(request_line: &[u8], prefix: &str) -> Vec<u8> {
+    match request_line.iter().position(|&b| b == b' ') {
+        Some(i) if request_line.get(i + 1) == Some(&b'/') => {
+            let mut rewritten = Vec::with_capacity(request_line.len() + prefix.len());
+            rewritten.extend_from_slice(&request_line[..i + 1]);
+            rewritten.extend_from_slice(prefix.as_bytes());
+            rewritten.extend_from_slice(&request_line[i + 1..]);
+            rewritten
+        }
+        _ => request_line.to_vec(),
+    }
+}
+
+impl Read for StreamConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for StreamConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_pending()?;
+
+        let mut request_line = match self.request_line.take() {
+            Some(request_line) => request_line,
+            None => return self.stream.write(buf),
+        };
+
+        // Buffer up to the end of the request line, and no further
+        let line_end = buf.iter().position(|&b| b == b'\n');
+        let consumed = line_end.map(|i| i + 1).unwrap_or(buf.len());
+        request_line.extend_from_slice(&buf[..consumed]);
+
+        if line_end.is_none() {
+            self.request_line = Some(request_line);
+            return Ok(consumed);
+        }
+
+        self.pending = add_path_prefix(&request_line, &self.path_prefix);
+
+        // The bytes are already accepted, so the rest can wait for the next
+        // write or flush if the stream isn't ready
+        match self.write_pending() {
+            Ok(()) => Ok(consumed),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(consumed),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.stream.flush()
+    }
+}
+
+impl AsyncRead for StreamConnection {}
+
+impl AsyncWrite for StreamConnection {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.write_pending() {
+            Ok(()) => AsyncWrite::shutdown(&mut self.stream),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn unsupported_stream_urls_are_rejected() {
+        let core = Core::new().unwrap();
+        let connector = |url: &str| StreamConnector::new(Some(&url.to_string()), &core.handle());
+
+        assert!(connector("ftp://localhost:8080").is_err());
+        assert!(connector("localhost:8080").is_err());
+        assert!(connector("/1.1/statuses/filter.json").is_err());
+
+        let redirect = connector("http://localhost:8080/twitter/").unwrap().redirect.unwrap();
+        assert_eq!(redirect.path_prefix, "/twitter");
+    }
+
+    #[test]
+    fn path_prefix_is_added_to_the_request_target() {
+        assert_eq!(
+            add_path_prefix(b"POST /1.1/statuses/filter.json HTTP/1.1\r\n", "/twitter"),
+            b"POST /twitter/1.1/statuses/filter.json HTTP/1.1\r\n".to_vec()
+        );
+        assert_eq!(
+            add_path_prefix(b"CONNECT stream.twitter.com:443 HTTP/1.1\r\n", "/twitter"),
+            b"CONNECT stream.twitter.com:443 HTTP/1.1\r\n".to_vec()
+        );
+    }
+}
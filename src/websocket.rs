@@ -1,12 +1,15 @@
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{Bytes, BytesMut};
-use prost::Message;
+use prost::{DecodeError, Message};
+use protobuf::RequestMessage;
 use tk_bufstream::Buf;
 
 const OPCODE_BINARY: u8 = 0x2;
 pub(crate) const EMPTY_PING: &[u8] = &[0x9 | 0x80, 0];
+// Control frames can't have longer payloads (RFC 6455 section 5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
 
-pub(crate) enum Frame<B>
+pub enum Frame<B>
 where
     B: AsRef<[u8]>,
 {
@@ -22,7 +25,7 @@ where
 //
 // Based on zero_copy.rs from tk-http.
 // https://github.com/swindon-rs/tk-http/blob/3520464/src/websocket/zero_copy.rs#L124-L162
-pub(crate) fn serialize_protobuf<M>(message: M) -> Option<Bytes>
+pub fn serialize_protobuf<M>(message: M) -> Option<Bytes>
 where
     M: Message,
 {
@@ -71,27 +74,39 @@ where
 
 // Copied from zero_copy.rs, but with mask removed
 // https://github.com/swindon-rs/tk-http/blob/3520464/src/websocket/zero_copy.rs#L164-L185
-pub(crate) fn write_close(buf: &mut Buf, code: u16, reason: &[u8]) {
+pub(crate) fn close_frame(code: u16, reason: &[u8]) -> Bytes {
     assert!(reason.len() <= 123);
-    buf.extend(&[
+    let mut frame = BytesMut::with_capacity(reason.len() + 4);
+    frame.extend_from_slice(&[
         0x88,
         (reason.len() + 2) as u8,
         (code >> 8) as u8,
         (code & 0xFF) as u8,
     ]);
-    buf.extend(reason);
+    frame.extend_from_slice(reason);
+    frame.freeze()
 }
 
-pub(crate) enum ErrorEnum {
+pub(crate) fn pong_frame(payload: &[u8]) -> Bytes {
+    assert!(payload.len() <= MAX_CONTROL_PAYLOAD);
+    let mut frame = BytesMut::with_capacity(payload.len() + 2);
+    frame.extend_from_slice(&[0x8A, payload.len() as u8]);
+    frame.extend_from_slice(payload);
+    frame.freeze()
+}
+
+#[derive(Debug)]
+pub enum ErrorEnum {
     TooLong,
     Fragmented,
     Unmasked,
     InvalidOpcode(u8),
+    ControlTooLong,
 }
 
 // Copied from zero_copy.rs
 // https://github.com/swindon-rs/tk-http/blob/3520464/src/websocket/zero_copy.rs#L55-122
-pub(crate) fn parse_frame<'a>(
+pub fn parse_frame<'a>(
     buf: &'a mut Buf,
     limit: usize,
     masked: bool,
@@ -113,6 +128,10 @@ pub(crate) fn parse_frame<'a>(
                 if buf.len() < 10 {
                     return Ok(None);
                 }
+                // The most significant bit must be 0 (RFC 6455 section 5.2)
+                if buf[2] & 0x80 != 0 {
+                    return Err(ErrorEnum::TooLong);
+                }
                 (BigEndian::read_u64(&buf[2..10]), 10)
             }
             size => (size as u64, 2),
@@ -123,7 +142,8 @@ pub(crate) fn parse_frame<'a>(
     }
     let size = size as usize;
     let start = fsize + if masked { 4 } else { 0 } /* mask size */;
-    if buf.len() < start + size {
+    // Written to avoid overflowing `start + size` with a huge limit
+    if buf.len() < start || buf.len() - start < size {
         return Ok(None);
     }
 
@@ -166,3 +186,169 @@ pub(crate) fn parse_frame<'a>(
     };
     return Ok(Some((frame, start + size)));
 }
+
+// What a client sent, other than frames that are just consumed (pongs)
+pub enum Incoming {
+    Request(RequestMessage),
+    // To be answered with a pong carrying the same payload
+    Ping(Bytes),
+    // To be answered with a close frame, after which the client sends nothing
+    Close(u16),
+    Pong,
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    Frame(ErrorEnum),
+    UnexpectedFrame,
+    // The frame is consumed, so reading can carry on after it
+    Decode(DecodeError, usize),
+}
+
+// Reads the next request or control frame from the start of a client's
+// input buffer, along with the number of bytes consumed.
+//
+// Frames larger than `limit` are rejected as soon as their header arrives,
+// without waiting for the rest of the frame to be buffered.
+pub fn read_request(
+    buf: &mut Buf,
+    limit: usize,
+) -> Result<Option<(Incoming, usize)>, RequestError> {
+    let (frame, amount_consumed) = match parse_frame(buf, limit, true) {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(RequestError::Frame(e)),
+    };
+
+    let incoming = match frame {
+        Frame::Binary(bytes) => {
+            let message = RequestMessage::decode(bytes)
+                .map_err(|e| RequestError::Decode(e, amount_consumed))?;
+            Incoming::Request(message)
+        }
+        Frame::Ping(payload) | Frame::Pong(payload) if payload.len() > MAX_CONTROL_PAYLOAD => {
+            return Err(RequestError::Frame(ErrorEnum::ControlTooLong))
+        }
+        // The reason follows a two byte code
+        Frame::Close(_, reason) if reason.len() + 2 > MAX_CONTROL_PAYLOAD => {
+            return Err(RequestError::Frame(ErrorEnum::ControlTooLong))
+        }
+        Frame::Ping(payload) => Incoming::Ping(Bytes::from(payload)),
+        Frame::Pong(_) => Incoming::Pong,
+        Frame::Close(code, _) => Incoming::Close(code),
+        Frame::Text(_) => return Err(RequestError::UnexpectedFrame),
+    };
+
+    Ok(Some((incoming, amount_consumed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::{FollowRequest, FollowStatusResponse, ResponseMessage};
+    use protobuf::request_message::Data as Request;
+    use protobuf::response_message::Data as Response;
+
+    const MAX_PACKET_SIZE: usize = 10 << 20;
+
+    // Boss names, plus a repeat count for padding, so that all three frame
+    // length encodings are covered
+    fn follow_status(names: Vec<String>, padding: u32) -> ResponseMessage {
+        let mut followed_boss_names = names;
+        followed_boss_names.push("x".repeat((padding % 100_000) as usize));
+
+        ResponseMessage {
+            data: Some(Response::FollowStatusMessage(FollowStatusResponse {
+                followed_boss_names,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn mask_frame(payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+        let mut frame = vec![0x82];
+
+        match payload.len() {
+            len @ 0...125 => frame.push(0x80 | len as u8),
+            len @ 126...65535 => {
+                frame.push(0x80 | 126);
+                frame.extend(&[(len >> 8) as u8, len as u8]);
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend((0..8).rev().map(|i| (len >> (i * 8)) as u8));
+            }
+        }
+
+        frame.extend(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    quickcheck! {
+        fn serialized_frames_round_trip(names: Vec<String>, padding: u32) -> bool {
+            let message = follow_status(names, padding);
+            let bytes = serialize_protobuf(message.clone()).unwrap();
+
+            let mut buf = Buf::new();
+            buf.extend(&bytes);
+
+            match parse_frame(&mut buf, MAX_PACKET_SIZE, false) {
+                Ok(Some((Frame::Binary(payload), consumed))) => {
+                    let decoded = ResponseMessage::decode(payload).ok();
+                    consumed == bytes.len() && decoded == Some(message)
+                }
+                _ => false,
+            }
+        }
+
+        fn partial_frames_are_incomplete(names: Vec<String>, padding: u32, cut: usize) -> bool {
+            let bytes = serialize_protobuf(follow_status(names, padding)).unwrap();
+
+            let mut buf = Buf::new();
+            buf.extend(&bytes[..cut % bytes.len()]);
+
+            match parse_frame(&mut buf, MAX_PACKET_SIZE, false) {
+                Ok(None) => true,
+                _ => false,
+            }
+        }
+
+        fn masked_requests_round_trip(names: Vec<String>, mask: (u8, u8, u8, u8)) -> bool {
+            let message = RequestMessage {
+                data: Some(Request::FollowMessage(FollowRequest {
+                    boss_names: names,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            };
+
+            let mut payload = Vec::new();
+            message.encode(&mut payload).unwrap();
+            let frame = mask_frame(&payload, [mask.0, mask.1, mask.2, mask.3]);
+
+            let mut buf = Buf::new();
+            buf.extend(&frame);
+
+            match read_request(&mut buf, MAX_PACKET_SIZE) {
+                Ok(Some((Incoming::Request(request), consumed))) => {
+                    consumed == frame.len() && request == message
+                }
+                _ => false,
+            }
+        }
+
+        fn arbitrary_input_does_not_panic(data: Vec<u8>, masked: bool) -> bool {
+            let mut buf = Buf::new();
+            buf.extend(&data);
+            let _ = parse_frame(&mut buf, MAX_PACKET_SIZE, masked);
+
+            let mut buf = Buf::new();
+            buf.extend(&data);
+            let _ = read_request(&mut buf, MAX_PACKET_SIZE);
+
+            true
+        }
+    }
+}
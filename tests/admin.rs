@@ -0,0 +1,65 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::{Config, CorsConfig, OriginList};
+use std::net::SocketAddr;
+use support::MockTwitter;
+
+const PATH: &str = "/api/admin/expiring_bosses.json";
+const ORIGIN: &str = "https://allowed.example";
+
+fn start_server(twitter: &MockTwitter) -> SocketAddr {
+    let mut config = Config::default();
+    config.admin_token = Some("secret".to_string());
+    config.cors = Some(CorsConfig {
+        allowed_origins: OriginList::new(&[ORIGIN]),
+        max_age_seconds: 86400,
+    });
+
+    support::start_server(twitter, config)
+}
+
+#[test]
+fn admin_endpoints_need_the_token() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter);
+
+    let response = support::http_request(server, "GET", PATH, &[]);
+    assert_eq!(response.status, 404);
+
+    let response = support::http_request(
+        server,
+        "GET",
+        PATH,
+        &[("Authorization", "Bearer secret"), ("Origin", ORIGIN)],
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"[]");
+    // Not callable from other origins, even allowed ones
+    assert!(response.header_values("Access-Control-Allow-Origin").is_empty());
+}
+
+#[test]
+fn admin_endpoints_reject_wrong_tokens() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter);
+
+    for &token in &["Bearer wrong", "Bearer secre", "Bearer secret2", "Basic secret", "secret"] {
+        let response = support::http_request(server, "GET", PATH, &[("Authorization", token)]);
+        assert_eq!(response.status, 404, "token {:?}", token);
+    }
+}
+
+#[test]
+fn admin_endpoints_are_disabled_without_a_token() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+
+    let response = support::http_request(server, "GET", PATH, &[("Authorization", "Bearer ")]);
+    assert_eq!(response.status, 404);
+}
@@ -0,0 +1,72 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::{Config, CorsConfig, OriginList};
+use std::net::SocketAddr;
+use support::MockTwitter;
+
+const ALLOWED: &str = "https://allowed.example";
+const DISALLOWED: &str = "https://other.example";
+
+fn start_server(twitter: &MockTwitter) -> SocketAddr {
+    let mut config = Config::default();
+    config.cors = Some(CorsConfig {
+        allowed_origins: OriginList::new(&[ALLOWED]),
+        max_age_seconds: 86400,
+    });
+
+    support::start_server(twitter, config)
+}
+
+fn preflight(server: SocketAddr, origin: &str) -> support::HttpResponse {
+    support::http_request(
+        server,
+        "OPTIONS",
+        "/api/bosses.json",
+        &[
+            ("Origin", origin),
+            ("Access-Control-Request-Method", "GET"),
+            ("Access-Control-Request-Headers", "X-Anything"),
+        ],
+    )
+}
+
+#[test]
+fn cors_headers_for_allowed_origins() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter);
+
+    let allowed = support::http_request(server, "GET", "/api/bosses.json", &[("Origin", ALLOWED)]);
+    assert_eq!(allowed.header_values("Access-Control-Allow-Origin"), vec![ALLOWED]);
+    assert!(allowed.header_values("Vary").contains(&"Origin"));
+
+    assert_eq!(
+        preflight(server, ALLOWED).header_values("Access-Control-Allow-Headers"),
+        vec!["Content-Type, If-None-Match"]
+    );
+}
+
+#[test]
+fn no_cors_headers_for_other_origins() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter);
+
+    // Shared caches must know that the response depends on the origin, even
+    // when it doesn't include CORS headers
+    for origin in &[None, Some(DISALLOWED)] {
+        let headers = origin.map(|origin| ("Origin", origin)).into_iter().collect::<Vec<_>>();
+        let response = support::http_request(server, "GET", "/api/bosses.json", &headers);
+        assert!(response.header_values("Access-Control-Allow-Origin").is_empty());
+        assert!(response.header_values("Vary").contains(&"Origin"));
+    }
+
+    let disallowed = preflight(server, DISALLOWED);
+    assert!(disallowed.header_values("Access-Control-Allow-Origin").is_empty());
+    assert!(disallowed.header_values("Access-Control-Allow-Methods").is_empty());
+    assert!(disallowed.header_values("Access-Control-Allow-Headers").is_empty());
+}
@@ -0,0 +1,145 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::Config;
+use petronel_gbfrf::protobuf::{FollowRequest, RaidTweetResponse, RequestMessage, UnfollowRequest};
+use petronel_gbfrf::protobuf::request_message::Data as Request;
+use petronel_gbfrf::protobuf::response_message::Data as Response;
+use support::{Language, MockTwitter, WebsocketClient};
+
+// Sends a request and waits for its reply, so that the request has been
+// handled before anything is tweeted
+fn request(client: &mut WebsocketClient, data: Request) {
+    client.send_message(RequestMessage {
+        data: Some(data),
+        request_id: "request".to_string(),
+    });
+
+    client.recv_message_matching(|m| m.request_id == "request");
+}
+
+fn recv_tweet(client: &mut WebsocketClient) -> RaidTweetResponse {
+    let message = client.recv_message_matching(|m| match m.data {
+        Some(Response::RaidTweetMessage(_)) => true,
+        _ => false,
+    });
+
+    match message.data {
+        Some(Response::RaidTweetMessage(tweet)) => tweet,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn followed_raid_tweets_are_delivered_from_the_stream() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = WebsocketClient::connect(server);
+
+    assert_eq!(client.recv_frame().opcode, 0x9);
+
+    request(
+        &mut client,
+        Request::FollowMessage(FollowRequest {
+            boss_names: vec!["Lvl 60 Ozorotter".to_string()],
+            ..Default::default()
+        }),
+    );
+
+    twitter.tweet(support::raid_tweet(
+        1,
+        "Lvl 60 Ozorotter",
+        "ABCD1234",
+        Language::English,
+    ));
+
+    let tweet = recv_tweet(&mut client);
+    assert_eq!(tweet.boss_name, "Lvl 60 Ozorotter");
+    assert_eq!(tweet.raid_id, "ABCD1234");
+    assert_eq!(tweet.screen_name, "user1");
+    assert_eq!(tweet.tweet_id, 1);
+}
+
+#[test]
+fn unfollowed_bosses_are_not_delivered() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = WebsocketClient::connect(server);
+
+    request(
+        &mut client,
+        Request::FollowMessage(FollowRequest {
+            boss_names: vec!["Lvl 60 Ozorotter".to_string(), "Lv60 オオゾラッコ".to_string()],
+            ..Default::default()
+        }),
+    );
+
+    twitter.tweet(support::raid_tweet(
+        1,
+        "Lv60 オオゾラッコ",
+        "AAAA1111",
+        Language::Japanese,
+    ));
+    assert_eq!(recv_tweet(&mut client).tweet_id, 1);
+
+    request(
+        &mut client,
+        Request::UnfollowMessage(UnfollowRequest {
+            boss_names: vec!["Lv60 オオゾラッコ".to_string()],
+        }),
+    );
+
+    // The tweet for the unfollowed boss is skipped, so the next one is for
+    // the boss that's still followed
+    twitter.tweet(support::raid_tweet(
+        2,
+        "Lv60 オオゾラッコ",
+        "BBBB2222",
+        Language::Japanese,
+    ));
+    twitter.tweet(support::raid_tweet(
+        3,
+        "Lvl 60 Ozorotter",
+        "CCCC3333",
+        Language::English,
+    ));
+
+    let tweet = recv_tweet(&mut client);
+    assert_eq!(tweet.boss_name, "Lvl 60 Ozorotter");
+    assert_eq!(tweet.tweet_id, 3);
+}
+
+#[test]
+fn stream_url_paths_are_added_to_request_paths() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.twitter_stream_url = Some(format!("{}/twitter/", twitter.url()));
+    let server = support::start_server(&twitter, config);
+    let mut client = WebsocketClient::connect(server);
+
+    request(
+        &mut client,
+        Request::FollowMessage(FollowRequest {
+            boss_names: vec!["Lvl 60 Ozorotter".to_string()],
+            ..Default::default()
+        }),
+    );
+
+    // Once a tweet arrives, the stream has been requested
+    twitter.tweet(support::raid_tweet(
+        1,
+        "Lvl 60 Ozorotter",
+        "ABCD1234",
+        Language::English,
+    ));
+    assert_eq!(recv_tweet(&mut client).tweet_id, 1);
+
+    let request_line = twitter.stream_request_line().unwrap();
+    let target = request_line.split(' ').nth(1).unwrap();
+    assert!(target.starts_with("/twitter/1.1/"), "{}", request_line);
+}
@@ -0,0 +1,721 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::Config;
+use petronel_gbfrf::protobuf::{AllRaidBossesRequest, ConnectionOptionsRequest, FollowFilter,
+                               FollowRequest, Language as ProtoLanguage, RaidBoss,
+                               RaidBossesRequest, RaidTweetResponse, RequestMessage,
+                               ResponseMessage, UnfollowRequest};
+use petronel_gbfrf::protobuf::error_response::Code as ErrorCode;
+use petronel_gbfrf::protobuf::request_message::Data as Request;
+use petronel_gbfrf::protobuf::response_message::Data as Response;
+use prost::Message;
+use std::time::Duration;
+use support::{Frame, Language, MockTwitter, WebsocketClient};
+
+const BOSS: &str = "Lvl 60 Ozorotter";
+const OTHER_BOSS: &str = "Lvl 75 Shiva";
+const JAPANESE_BOSS: &str = "Lv75 シュヴァリエ・マグナ";
+
+fn all_bosses(client: &mut WebsocketClient) -> Vec<RaidBoss> {
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+
+    match client.recv_data() {
+        Response::RaidBossesMessage(response) => response.raid_bosses,
+        other => panic!("expected boss list, got {:?}", other),
+    }
+}
+
+fn expect_boss_update(client: &mut WebsocketClient) -> RaidBoss {
+    match client.recv_data() {
+        Response::RaidBossesMessage(mut response) => {
+            assert_eq!(response.raid_bosses.len(), 1);
+            response.raid_bosses.remove(0)
+        }
+        other => panic!("expected boss update, got {:?}", other),
+    }
+}
+
+fn expect_tweet(client: &mut WebsocketClient) -> RaidTweetResponse {
+    match client.recv_data() {
+        Response::RaidTweetMessage(tweet) => tweet,
+        other => panic!("expected tweet, got {:?}", other),
+    }
+}
+
+// Connects and waits until the connection is subscribed to updates, which
+// happens asynchronously after the handshake
+fn connect(server: std::net::SocketAddr) -> WebsocketClient {
+    let mut client = WebsocketClient::connect(server);
+    assert_eq!(client.recv_frame().opcode, 0x9);
+    all_bosses(&mut client);
+    client
+}
+
+#[test]
+fn connection_starts_with_ping() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = WebsocketClient::connect(server);
+
+    let ping = Frame {
+        opcode: 0x9,
+        header_len: 2,
+        payload: vec![],
+    };
+    assert_eq!(client.recv_frame(), ping);
+
+    assert_eq!(all_bosses(&mut client), vec![]);
+}
+
+#[test]
+fn new_bosses_are_broadcast_and_listed() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "ABCD1234", Language::English));
+
+    let boss = expect_boss_update(&mut client);
+    assert_eq!(boss.name, BOSS);
+    assert_eq!(boss.level, 60);
+    assert_eq!(boss.language, ProtoLanguage::English as i32);
+
+    let names = all_bosses(&mut client)
+        .into_iter()
+        .map(|boss| boss.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![BOSS.to_string()]);
+}
+
+#[test]
+fn follow_unfollow_and_tweet_history() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "AAAA1111", Language::English));
+    assert_eq!(expect_boss_update(&mut client).name, BOSS);
+
+    // Following sends the existing tweets for the boss, then new ones
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names: vec![BOSS.to_string()],
+        ..Default::default()
+    }));
+    assert_eq!(expect_tweet(&mut client).raid_id, "AAAA1111");
+
+    twitter.tweet(support::raid_tweet(2, BOSS, "BBBB2222", Language::English));
+    assert_eq!(expect_tweet(&mut client).raid_id, "BBBB2222");
+
+    client.send(Request::UnfollowMessage(UnfollowRequest {
+        boss_names: vec![BOSS.to_string()],
+    }));
+
+    // History is still available after unfollowing. Since requests are
+    // handled in order, this response also means the unfollow has happened.
+    client.send(Request::RaidBossesMessage(RaidBossesRequest {
+        boss_names: vec![BOSS.to_string()],
+    }));
+    let mut history = vec![
+        expect_tweet(&mut client).tweet_id,
+        expect_tweet(&mut client).tweet_id,
+    ];
+    history.sort();
+    assert_eq!(history, vec![1, 2]);
+
+    // The tweet for the unfollowed boss is skipped, so the next message is
+    // the update for the other boss
+    twitter.tweet(support::raid_tweet(3, BOSS, "CCCC3333", Language::English));
+    twitter.tweet(support::raid_tweet(
+        4,
+        OTHER_BOSS,
+        "DDDD4444",
+        Language::English,
+    ));
+    assert_eq!(expect_boss_update(&mut client).name, OTHER_BOSS);
+}
+
+#[test]
+fn heartbeats_are_sent_as_keep_alive_messages() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.heartbeat_interval = Duration::from_secs(1);
+    let server = support::start_server(&twitter, config);
+    let mut client = WebsocketClient::connect(server);
+
+    assert_eq!(client.recv_frame().opcode, 0x9);
+
+    for _ in 0..2 {
+        match client.recv_data() {
+            Response::KeepAliveMessage(_) => {}
+            other => panic!("expected keep-alive, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn frames_use_the_shortest_length_encoding() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    // Less than 126 bytes: length fits in the second byte
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+    let frame = client.recv_frame();
+    assert_eq!(frame.header_len, 2);
+    assert!(frame.payload.len() < 126);
+
+    // Up to 65535 bytes: 16-bit extended length
+    let medium_name = format!("Lvl 100 {}", "M".repeat(200));
+    twitter.tweet(support::raid_tweet(
+        1,
+        &medium_name,
+        "AAAA0000",
+        Language::English,
+    ));
+    let frame = client.recv_frame();
+    assert_eq!(frame.header_len, 4);
+    assert!(frame.payload.len() >= 126 && frame.payload.len() <= 65535);
+
+    // Larger: 64-bit extended length
+    let boss_count = 80;
+    for i in 0..boss_count {
+        let name = format!("Lvl 100 {}{}", "L".repeat(1000), i);
+        let raid_id = format!("{:08X}", i);
+        twitter.tweet(support::raid_tweet(2 + i, &name, &raid_id, Language::English));
+        expect_boss_update(&mut client);
+    }
+
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+    let frame = client.recv_frame();
+    assert_eq!(frame.header_len, 10);
+    assert!(frame.payload.len() > 65535);
+
+    let message = petronel_gbfrf::protobuf::ResponseMessage::decode(frame.payload).unwrap();
+    match message.data {
+        Some(Response::RaidBossesMessage(response)) => {
+            assert_eq!(response.raid_bosses.len(), boss_count as usize + 1)
+        }
+        other => panic!("expected boss list, got {:?}", other),
+    }
+}
+
+#[test]
+fn oversized_requests_are_closed_before_being_buffered() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    // Only the header is sent, declaring a 1 MiB payload
+    client.send_raw(&[0x82, 0x80 | 127, 0, 0, 0, 0, 0, 0x10, 0, 0, 1, 2, 3, 4]);
+
+    let frame = client.recv_frame();
+    assert_eq!(frame.opcode, 0x8);
+    assert_eq!(&frame.payload[..2], &[0x03, 0xF1]); // 1009
+}
+
+#[test]
+fn silent_clients_are_pinged_then_disconnected() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.ping_interval = Duration::from_secs(1);
+    config.ping_timeout = Duration::from_secs(2);
+    let server = support::start_server(&twitter, config);
+    let mut client = WebsocketClient::connect(server);
+
+    // Initial ping, then periodic ones that never get a pong
+    let mut pings = 0;
+    let close = loop {
+        let frame = client.recv_frame();
+        match frame.opcode {
+            0x9 => pings += 1,
+            0x8 => break frame,
+            _ => {}
+        }
+    };
+
+    assert!(pings >= 2);
+    assert_eq!(&close.payload[..2], &[0x03, 0xE9]); // 1001
+}
+
+#[test]
+fn pongs_keep_connections_alive() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.ping_interval = Duration::from_secs(1);
+    config.ping_timeout = Duration::from_secs(2);
+    let server = support::start_server(&twitter, config);
+    let mut client = WebsocketClient::connect(server);
+
+    for _ in 0..5 {
+        assert_eq!(client.recv_frame().opcode, 0x9);
+        client.send_frame(0xA, &[]);
+    }
+}
+
+#[test]
+fn client_pings_are_answered_with_pongs() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    client.send_frame(0x9, b"hello");
+
+    let pong = client.recv_frame();
+    assert_eq!(pong.opcode, 0xA);
+    assert_eq!(&pong.payload[..], b"hello");
+}
+
+#[test]
+fn client_close_frames_are_echoed() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    client.send_frame(0x8, &[0x03, 0xE9]); // 1001
+
+    let close = client.recv_frame();
+    assert_eq!(close.opcode, 0x8);
+    assert_eq!(&close.payload[..], &[0x03, 0xE9]);
+}
+
+#[test]
+fn large_boss_lists_are_split_into_chunks() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.boss_list_chunk_size = 1024;
+    let server = support::start_server(&twitter, config);
+    let mut client = connect(server);
+
+    let boss_count = 10;
+    for i in 0..boss_count {
+        let name = format!("Lvl 100 {}{}", "C".repeat(300), i);
+        let raid_id = format!("{:08X}", i);
+        twitter.tweet(support::raid_tweet(i, &name, &raid_id, Language::English));
+        expect_boss_update(&mut client);
+    }
+
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+
+    let mut chunks = vec![];
+    loop {
+        match client.recv_data() {
+            Response::RaidBossesMessage(response) => {
+                let partial = response.partial;
+                chunks.push(response);
+                if !partial {
+                    break;
+                }
+            }
+            other => panic!("expected boss list, got {:?}", other),
+        }
+    }
+
+    assert!(chunks.len() > 1);
+    let sync_timestamp = chunks[0].sync_timestamp;
+    assert!(chunks.iter().all(|c| c.sync_timestamp == sync_timestamp));
+    let total: usize = chunks.iter().map(|c| c.raid_bosses.len()).sum();
+    assert_eq!(total, boss_count as usize);
+}
+
+#[test]
+fn boss_lists_can_be_synced_incrementally() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "ABCD1234", Language::English));
+    expect_boss_update(&mut client);
+    std::thread::sleep(Duration::from_millis(10));
+
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+    let since = match client.recv_data() {
+        Response::RaidBossesMessage(response) => {
+            assert_eq!(response.raid_bosses.len(), 1);
+            response.sync_timestamp
+        }
+        other => panic!("expected boss list, got {:?}", other),
+    };
+
+    std::thread::sleep(Duration::from_millis(10));
+    twitter.tweet(support::raid_tweet(2, OTHER_BOSS, "EFGH5678", Language::English));
+    expect_boss_update(&mut client);
+
+    client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest { since }));
+    match client.recv_data() {
+        Response::RaidBossesMessage(response) => {
+            let names: Vec<_> = response.raid_bosses.iter().map(|b| &b.name).collect();
+            assert_eq!(names, vec![OTHER_BOSS]);
+            assert!(response.sync_timestamp >= since);
+            assert!(!response.partial);
+        }
+        other => panic!("expected boss list, got {:?}", other),
+    }
+}
+
+#[test]
+fn follow_filters_apply_to_broadcasts() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names: vec![],
+        filter: Some(FollowFilter {
+            language: ProtoLanguage::English as i32,
+            min_level: 70,
+        }),
+        ..Default::default()
+    }));
+    // Requests are handled in order, so the filter is in place after this
+    all_bosses(&mut client);
+
+    twitter.tweet(support::raid_tweet(1, JAPANESE_BOSS, "AAAA1111", Language::Japanese));
+    twitter.tweet(support::raid_tweet(2, BOSS, "BBBB2222", Language::English));
+    twitter.tweet(support::raid_tweet(3, OTHER_BOSS, "CCCC3333", Language::English));
+
+    assert_eq!(expect_boss_update(&mut client).name, OTHER_BOSS);
+}
+
+#[test]
+fn follow_filter_levels_are_clamped() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+    let mut unfiltered_client = connect(server);
+
+    let filter = |min_level| {
+        Request::FollowMessage(FollowRequest {
+            boss_names: vec![],
+            filter: Some(FollowFilter {
+                language: ProtoLanguage::Unspecified as i32,
+                min_level,
+            }),
+            ..Default::default()
+        })
+    };
+
+    // Would wrap around to -1 if truncated to 16 bits, letting everything through
+    client.send(filter(65535));
+    all_bosses(&mut client);
+
+    // Broadcasts reach every connection at once, so once the other client has
+    // the update, it has been skipped for this one
+    twitter.tweet(support::raid_tweet(1, BOSS, "AAAA1111", Language::English));
+    assert_eq!(expect_boss_update(&mut unfiltered_client).name, BOSS);
+
+    client.send(filter(0));
+    all_bosses(&mut client);
+
+    twitter.tweet(support::raid_tweet(2, OTHER_BOSS, "BBBB2222", Language::English));
+    assert_eq!(expect_boss_update(&mut client).name, OTHER_BOSS);
+}
+
+#[test]
+fn duplicate_raids_are_reported_instead_of_resent() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    client.send(Request::ConnectionOptionsMessage(ConnectionOptionsRequest {
+        dedupe_window_seconds: 60,
+    }));
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names: vec![BOSS.to_string()],
+        ..Default::default()
+    }));
+    // The boss hasn't been seen yet, but is followed anyway
+    match client.recv_data() {
+        Response::FollowStatusMessage(status) => {
+            assert_eq!(status.followed_boss_names, vec![BOSS]);
+        }
+        other => panic!("expected follow status, got {:?}", other),
+    }
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "AAAA1111", Language::English));
+    expect_boss_update(&mut client);
+    assert_eq!(expect_tweet(&mut client).tweet_id, 1);
+
+    for tweet_id in 2..4 {
+        twitter.tweet(support::raid_tweet(tweet_id, BOSS, "AAAA1111", Language::English));
+        match client.recv_data() {
+            Response::RaidDuplicateMessage(duplicate) => {
+                assert_eq!(duplicate.raid_id, "AAAA1111");
+                assert_eq!(duplicate.boss_name, BOSS);
+                assert_eq!(duplicate.count, tweet_id as i32);
+            }
+            other => panic!("expected raid duplicate, got {:?}", other),
+        }
+    }
+
+    // Other raids are unaffected
+    twitter.tweet(support::raid_tweet(4, BOSS, "BBBB2222", Language::English));
+    assert_eq!(expect_tweet(&mut client).raid_id, "BBBB2222");
+}
+
+// Sends a request with an ID, and waits for its reply. Since requests are
+// handled in order, anything sent after the reply happened after the request.
+fn request(client: &mut WebsocketClient, data: Request, request_id: &str) -> ResponseMessage {
+    client.send_message(RequestMessage {
+        data: Some(data),
+        request_id: request_id.to_string(),
+    });
+
+    client.recv_message_matching(|message| message.request_id == request_id)
+}
+
+// Tweets an English and a Japanese boss with the same image, and waits until
+// petronel has paired them up as translations
+fn tweet_translated_bosses(twitter: &MockTwitter, client: &mut WebsocketClient) {
+    twitter.tweet(support::raid_tweet_with_image(
+        1,
+        OTHER_BOSS,
+        "AAAA1111",
+        Language::English,
+    ));
+    twitter.tweet(support::raid_tweet_with_image(
+        2,
+        JAPANESE_BOSS,
+        "BBBB2222",
+        Language::Japanese,
+    ));
+
+    client.recv_message_matching(|message| match message.data {
+        Some(Response::RaidBossesMessage(ref response)) => response.raid_bosses.iter().any(|boss| {
+            boss.name == JAPANESE_BOSS
+                && boss.translated_name.as_ref().map(String::as_str) == Some(OTHER_BOSS)
+        }),
+        _ => false,
+    });
+}
+
+#[test]
+fn following_with_translations_delivers_translated_tweets_until_unfollowed() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+    tweet_translated_bosses(&twitter, &mut client);
+
+    let follow = Request::FollowMessage(FollowRequest {
+        boss_names: vec![JAPANESE_BOSS.to_string()],
+        follow_translations: true,
+        ..Default::default()
+    });
+    request(&mut client, follow, "follow");
+
+    let sentinel = Request::FollowMessage(FollowRequest {
+        boss_names: vec![BOSS.to_string()],
+        ..Default::default()
+    });
+    request(&mut client, sentinel, "sentinel");
+
+    twitter.tweet(support::raid_tweet(3, OTHER_BOSS, "CCCC3333", Language::English));
+    let tweet = client.recv_message_matching(|message| match message.data {
+        Some(Response::RaidTweetMessage(ref tweet)) => tweet.tweet_id == 3,
+        _ => false,
+    });
+    match tweet.data {
+        Some(Response::RaidTweetMessage(tweet)) => assert_eq!(tweet.boss_name, OTHER_BOSS),
+        other => panic!("expected tweet, got {:?}", other),
+    }
+
+    let unfollow = Request::UnfollowMessage(UnfollowRequest {
+        boss_names: vec![JAPANESE_BOSS.to_string()],
+    });
+    match request(&mut client, unfollow, "unfollow").data {
+        Some(Response::AckMessage(_)) => {}
+        other => panic!("expected ack, got {:?}", other),
+    }
+
+    // The tweet for the translation is skipped, so the next new tweet is the
+    // one for the boss that's still followed
+    twitter.tweet(support::raid_tweet(4, OTHER_BOSS, "DDDD4444", Language::English));
+    twitter.tweet(support::raid_tweet(5, BOSS, "EEEE5555", Language::English));
+    let tweet = client.recv_message_matching(|message| match message.data {
+        Some(Response::RaidTweetMessage(ref tweet)) => tweet.tweet_id >= 4,
+        _ => false,
+    });
+    match tweet.data {
+        Some(Response::RaidTweetMessage(tweet)) => assert_eq!(tweet.tweet_id, 5),
+        other => panic!("expected tweet, got {:?}", other),
+    }
+}
+
+#[test]
+fn translations_followed_implicitly_cannot_be_unfollowed_alone() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+    tweet_translated_bosses(&twitter, &mut client);
+
+    let follow = Request::FollowMessage(FollowRequest {
+        boss_names: vec![JAPANESE_BOSS.to_string()],
+        follow_translations: true,
+        ..Default::default()
+    });
+    request(&mut client, follow, "follow");
+
+    let unfollow = Request::UnfollowMessage(UnfollowRequest {
+        boss_names: vec![OTHER_BOSS.to_string()],
+    });
+    match request(&mut client, unfollow, "unfollow").data {
+        Some(Response::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::FollowedAsTranslation as i32);
+            assert!(error.message.contains(OTHER_BOSS));
+        }
+        other => panic!("expected error, got {:?}", other),
+    }
+
+    // Still followed
+    twitter.tweet(support::raid_tweet(3, OTHER_BOSS, "CCCC3333", Language::English));
+    client.recv_message_matching(|message| match message.data {
+        Some(Response::RaidTweetMessage(ref tweet)) => tweet.tweet_id == 3,
+        _ => false,
+    });
+}
+
+#[test]
+fn requests_are_acknowledged_and_errors_reported() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, Config::default());
+    let mut client = connect(server);
+
+    // Not a valid protobuf message, but the connection stays open
+    client.send_frame(0x2, &[0xFF, 0xFF, 0xFF]);
+    match client.recv_data() {
+        Response::ErrorMessage(error) => {
+            assert_eq!(error.code, ErrorCode::MalformedRequest as i32);
+            assert!(!error.message.is_empty());
+        }
+        other => panic!("expected error, got {:?}", other),
+    }
+
+    client.send_message(RequestMessage {
+        data: None,
+        request_id: "empty".to_string(),
+    });
+    let message = client.recv_message();
+    assert_eq!(message.request_id, "empty");
+    match message.data {
+        Some(Response::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::EmptyRequest as i32)
+        }
+        other => panic!("expected error, got {:?}", other),
+    }
+
+    client.send_message(RequestMessage {
+        data: Some(Request::UnfollowMessage(UnfollowRequest {
+            boss_names: vec![BOSS.to_string()],
+        })),
+        request_id: "unfollow".to_string(),
+    });
+    let message = client.recv_message();
+    assert_eq!(message.request_id, "unfollow");
+    match message.data {
+        Some(Response::AckMessage(_)) => {}
+        other => panic!("expected ack, got {:?}", other),
+    }
+
+    // Boss lists built by the server carry the ID, in place of an ack
+    client.send_message(RequestMessage {
+        data: Some(Request::AllRaidBossesMessage(AllRaidBossesRequest { since: 1 })),
+        request_id: "since".to_string(),
+    });
+    let message = client.recv_message();
+    assert_eq!(message.request_id, "since");
+    match message.data {
+        Some(Response::RaidBossesMessage(_)) => {}
+        other => panic!("expected boss list, got {:?}", other),
+    }
+
+    // Requests must be binary frames
+    client.send_frame(0x1, b"{}");
+    let close = client.recv_frame();
+    assert_eq!(close.opcode, 0x8);
+    assert_eq!(&close.payload[..2], &[0x03, 0xEB]); // 1003
+    assert_eq!(&close.payload[2..], b"unexpected frame");
+}
+
+#[test]
+fn unknown_follows_are_reported_with_suggestions() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.reject_unknown_follows = true;
+    let server = support::start_server(&twitter, config);
+    let mut client = connect(server);
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "AAAA1111", Language::English));
+    expect_boss_update(&mut client);
+
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names: vec![
+            BOSS.to_string(),
+            "lvl 60 ozorotter".to_string(),
+            "Lvl 60 Ozorottr".to_string(),
+            "Lvl 120 Nobody".to_string(),
+        ],
+        ..Default::default()
+    }));
+
+    let status = match client.recv_data() {
+        Response::FollowStatusMessage(status) => status,
+        other => panic!("expected follow status, got {:?}", other),
+    };
+    assert_eq!(status.followed_boss_names, vec![BOSS]);
+
+    let unknown = status
+        .unknown_bosses
+        .iter()
+        .map(|boss| (boss.name.as_str(), boss.suggestions.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        unknown,
+        vec![
+            ("lvl 60 ozorotter", vec![BOSS.to_string()]),
+            ("Lvl 60 Ozorottr", vec![BOSS.to_string()]),
+            ("Lvl 120 Nobody", vec![]),
+        ]
+    );
+
+    // The known boss is followed as usual
+    assert_eq!(expect_tweet(&mut client).raid_id, "AAAA1111");
+}
+
+#[test]
+fn rejected_follows_are_not_followed() {
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.reject_unknown_follows = true;
+    let server = support::start_server(&twitter, config);
+    let mut client = connect(server);
+
+    twitter.tweet(support::raid_tweet(1, BOSS, "AAAA1111", Language::English));
+    expect_boss_update(&mut client);
+
+    // Only the first few unknown names get suggestions
+    let boss_names = (0..20).map(|_| "Lvl 60 Ozorottr".to_string()).collect();
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names,
+        ..Default::default()
+    }));
+    let status = match client.recv_data() {
+        Response::FollowStatusMessage(status) => status,
+        other => panic!("expected follow status, got {:?}", other),
+    };
+    assert!(status.followed_boss_names.is_empty());
+    assert_eq!(status.unknown_bosses.len(), 20);
+    assert!(!status.unknown_bosses[0].suggestions.is_empty());
+    assert!(status.unknown_bosses[19].suggestions.is_empty());
+
+    // Once the boss appears, its tweets still aren't sent
+    twitter.tweet(support::raid_tweet(2, "Lvl 60 Ozorottr", "BBBB2222", Language::English));
+    expect_boss_update(&mut client);
+    twitter.tweet(support::raid_tweet(3, "Lvl 60 Ozorottr", "CCCC3333", Language::English));
+    twitter.tweet(support::raid_tweet(4, OTHER_BOSS, "DDDD4444", Language::English));
+    assert_eq!(expect_boss_update(&mut client).name, OTHER_BOSS);
+}
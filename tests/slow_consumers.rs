@@ -0,0 +1,161 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::protobuf::{AllRaidBossesRequest, FollowRequest, RaidBossesRequest,
+                               ResponseMessage};
+use petronel_gbfrf::protobuf::request_message::Data as Request;
+use petronel_gbfrf::protobuf::response_message::Data as Response;
+use petronel_gbfrf::{Config, OutputLimits, SlowConsumerPolicy};
+use prost::Message;
+use std::net::SocketAddr;
+use support::{Frame, Language, MockTwitter, WebsocketClient};
+
+// Large enough that a few hundred tweets fill the socket buffers between us
+// and the server, after which frames start to queue up in the outbox
+const NAME_PADDING: usize = 10_000;
+const TWEET_COUNT: u64 = 1000;
+const SMALL_BOSS: &str = "Lvl 75 Shiva";
+
+fn big_boss() -> String {
+    format!("Lvl 100 {}", "X".repeat(NAME_PADDING))
+}
+
+fn start_server(twitter: &MockTwitter, policy: SlowConsumerPolicy) -> SocketAddr {
+    let mut config = Config::default();
+    config.limits.output = OutputLimits {
+        max_bytes: 256 * 1024,
+        policy,
+        ..OutputLimits::default()
+    };
+
+    support::start_server(twitter, config)
+}
+
+// Connects and follows a boss, without reading the reply
+fn follow(server: SocketAddr, boss: &str) -> WebsocketClient {
+    let mut client = WebsocketClient::connect(server);
+    assert_eq!(client.recv_frame().opcode, 0x9);
+    client.send(Request::FollowMessage(FollowRequest {
+        boss_names: vec![boss.to_string()],
+        ..Default::default()
+    }));
+    client
+}
+
+fn tweet_big_bosses(twitter: &MockTwitter, count: u64) {
+    let boss = big_boss();
+    for i in 0..count {
+        let raid_id = format!("{:08X}", i);
+        twitter.tweet(support::raid_tweet(i, &boss, &raid_id, Language::English));
+    }
+}
+
+// Reads frames until the server closes the connection, returning the number
+// of tweets received and the close frame
+fn read_until_close(client: &mut WebsocketClient) -> (usize, Frame) {
+    let mut tweets = 0;
+    loop {
+        let frame = client.recv_frame();
+        match frame.opcode {
+            0x8 => return (tweets, frame),
+            0x2 => {
+                let message = ResponseMessage::decode(frame.payload).unwrap();
+                if let Some(Response::RaidTweetMessage(_)) = message.data {
+                    tweets += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn assert_slow_consumer_close(close: &Frame) {
+    assert_eq!(&close.payload[..2], &[0x03, 0xF0]); // 1008
+    assert_eq!(&close.payload[2..], b"slow consumer");
+}
+
+// The client is closed once too much is queued
+#[test]
+fn slow_consumers_are_disconnected() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter, SlowConsumerPolicy::Disconnect);
+    let mut client = follow(server, &big_boss());
+    tweet_big_bosses(&twitter, TWEET_COUNT);
+
+    let (tweets, close) = read_until_close(&mut client);
+    assert!(tweets < TWEET_COUNT as usize);
+    assert_slow_consumer_close(&close);
+}
+
+// Tweets are skipped, but the connection and boss updates survive
+#[test]
+fn tweets_to_slow_consumers_are_dropped() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter, SlowConsumerPolicy::Drop);
+    let mut client = follow(server, &big_boss());
+    tweet_big_bosses(&twitter, TWEET_COUNT);
+    twitter.tweet(support::raid_tweet(
+        TWEET_COUNT,
+        SMALL_BOSS,
+        "FFFFFFFF",
+        Language::English,
+    ));
+
+    let mut tweets = 0;
+    loop {
+        let frame = client.recv_frame();
+        assert_ne!(frame.opcode, 0x8, "connection closed");
+        if frame.opcode != 0x2 {
+            continue;
+        }
+
+        let message = ResponseMessage::decode(frame.payload).unwrap();
+        match message.data {
+            Some(Response::RaidTweetMessage(_)) => tweets += 1,
+            Some(Response::RaidBossesMessage(ref response))
+                if response.raid_bosses.iter().any(|boss| boss.name == SMALL_BOSS) =>
+            {
+                break
+            }
+            _ => {}
+        }
+    }
+    assert!(tweets > 0 && tweets < TWEET_COUNT as usize);
+}
+
+// Replies the client asked for can't be dropped, so if they pile up the
+// client is closed at twice the byte limit
+#[test]
+fn slow_consumers_piling_up_replies_are_disconnected() {
+    let twitter = MockTwitter::start();
+    let server = start_server(&twitter, SlowConsumerPolicy::Drop);
+    let mut client = follow(server, SMALL_BOSS);
+    // Wait until the tweets have been stored, using a client that does read
+    let mut history_client = follow(server, &big_boss());
+    history_client.send(Request::AllRaidBossesMessage(AllRaidBossesRequest::default()));
+    history_client.recv_message_matching(|message| match message.data {
+        Some(Response::RaidBossesMessage(_)) => true,
+        _ => false,
+    });
+    tweet_big_bosses(&twitter, 15);
+    for _ in 0..15 {
+        history_client.recv_message_matching(|message| match message.data {
+            Some(Response::RaidTweetMessage(_)) => true,
+            _ => false,
+        });
+    }
+
+    for _ in 0..200 {
+        client.send(Request::RaidBossesMessage(RaidBossesRequest {
+            boss_names: vec![big_boss()],
+        }));
+    }
+
+    let (_, close) = read_until_close(&mut client);
+    assert_slow_consumer_close(&close);
+}
@@ -0,0 +1,40 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::Config;
+use std::env;
+use std::fs;
+use support::MockTwitter;
+
+#[test]
+fn static_files_and_single_page_app_routes() {
+    let dir = env::temp_dir().join(format!("gbfrf-static-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("index.html"), "<html></html>").unwrap();
+    fs::write(dir.join("main file.js"), "main()").unwrap();
+
+    let twitter = MockTwitter::start();
+    let mut config = Config::default();
+    config.static_dir = Some(dir.clone());
+    let server = support::start_server(&twitter, config);
+    let get = |path: &str| support::http_request(server, "GET", path, &[]);
+
+    assert_eq!(get("/main%20file.js").body, b"main()");
+    assert_eq!(get("/main%2").status, 404);
+
+    // Client-side routes get the main page, but unknown API routes don't
+    assert_eq!(get("/raids/settings").body, b"<html></html>");
+    assert_eq!(get("/api/unknown").status, 404);
+
+    let head = support::http_request(server, "HEAD", "/", &[]);
+    assert_eq!(head.status, 200);
+    let post = support::http_request(server, "POST", "/", &[]);
+    assert_eq!(post.status, 404);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
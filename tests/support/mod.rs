@@ -0,0 +1,511 @@
+// Test helpers: a fake Twitter streaming API, a way to start the server
+// against it, and a minimal websocket client.
+
+#![allow(dead_code)]
+
+use petronel::Token;
+use petronel_gbfrf::{self, Config};
+use petronel_gbfrf::protobuf::{self, RequestMessage, ResponseMessage};
+use petronel_gbfrf::protobuf::response_message::Data as Response;
+use prost::Message;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const READ_TIMEOUT_SECONDS: u64 = 10;
+
+// Tweets from any other source are ignored
+const GAME_SOURCE: &str =
+    "<a href=\"http://granbluefantasy.jp/\" rel=\"nofollow\">グランブルー ファンタジー</a>";
+
+// Path of the boss image in tweets from `raid_tweet_with_image`. Every
+// boss with it has the same image, so petronel pairs them up as translations.
+const BOSS_IMAGE_PATH: &str = "/media/boss.png";
+const BOSS_IMAGE: &[u8] = include_bytes!("../fixtures/boss.png");
+
+// Serves a single streaming response, writing each tweet as it's sent by the
+// test, and the boss image to anything that asks for it
+pub struct MockTwitter {
+    addr: SocketAddr,
+    tweets: mpsc::Sender<String>,
+    stream_request_line: Arc<Mutex<Option<String>>>,
+}
+
+impl MockTwitter {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tweets, receiver) = mpsc::channel::<String>();
+        let receiver = Arc::new(Mutex::new(Some(receiver)));
+        let stream_request_line = Arc::new(Mutex::new(None));
+
+        let request_line = stream_request_line.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let receiver = receiver.clone();
+            let request_line = request_line.clone();
+            thread::spawn(move || serve_request(stream.unwrap(), &receiver, &request_line));
+        });
+
+        MockTwitter {
+            addr,
+            tweets,
+            stream_request_line,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn tweet(&self, tweet: String) {
+        self.tweets.send(tweet).unwrap();
+    }
+
+    // The request line the stream was requested with, once it has been
+    pub fn stream_request_line(&self) -> Option<String> {
+        self.stream_request_line.lock().unwrap().clone()
+    }
+}
+
+fn serve_request(
+    mut stream: TcpStream,
+    tweets: &Mutex<Option<mpsc::Receiver<String>>>,
+    stream_request_line: &Mutex<Option<String>>,
+) {
+    // Read the request line, and skip the headers
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+        line.clear();
+    }
+
+    if request_line.contains(BOSS_IMAGE_PATH) {
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: image/png\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            BOSS_IMAGE.len()
+        ).and_then(|()| stream.write_all(BOSS_IMAGE));
+        return;
+    }
+
+    let receiver = match tweets.lock().unwrap().take() {
+        Some(receiver) => receiver,
+        None => panic!("unexpected second stream request: {}", request_line.trim()),
+    };
+    *stream_request_line.lock().unwrap() = Some(request_line.trim().to_string());
+
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: application/json\r\n\
+              Transfer-Encoding: chunked\r\n\r\n",
+        )
+        .unwrap();
+
+    for tweet in receiver {
+        let chunk = format!("{}\r\n", tweet);
+        let written = write!(stream, "{:x}\r\n{}\r\n", chunk.len(), chunk)
+            .and_then(|()| stream.flush());
+
+        if written.is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Language {
+    English,
+    Japanese,
+}
+
+// JSON for a raid request tweet, as sent by the game
+pub fn raid_tweet(tweet_id: u64, boss_name: &str, raid_id: &str, language: Language) -> String {
+    tweet_json(tweet_id, boss_name, raid_id, language, None).to_string()
+}
+
+// A raid request tweet with the boss image attached, which is served by
+// `MockTwitter`
+pub fn raid_tweet_with_image(
+    tweet_id: u64,
+    boss_name: &str,
+    raid_id: &str,
+    language: Language,
+) -> String {
+    let media_url = format!("pbs.twimg.com{}", BOSS_IMAGE_PATH);
+    let size = json!({ "w": 640, "h": 480, "resize": "fit" });
+    let media = json!({
+        "id": 2000 + tweet_id,
+        "id_str": (2000 + tweet_id).to_string(),
+        "indices": [0, 0],
+        "media_url": format!("http://{}", media_url),
+        "media_url_https": format!("https://{}", media_url),
+        "url": "https://t.co/x",
+        "display_url": "pic.twitter.com/x",
+        "expanded_url": "https://twitter.com/user/status/1/photo/1",
+        "type": "photo",
+        "sizes": { "thumb": size, "small": size, "medium": size, "large": size },
+    });
+
+    tweet_json(tweet_id, boss_name, raid_id, language, Some(json!([media]))).to_string()
+}
+
+fn tweet_json(
+    tweet_id: u64,
+    boss_name: &str,
+    raid_id: &str,
+    language: Language,
+    media: Option<serde_json::Value>,
+) -> serde_json::Value {
+    let (text, lang) = match language {
+        Language::English => (
+            format!("{} :Battle ID\nI need backup!\n{}\nhttps://t.co/x", raid_id, boss_name),
+            "en",
+        ),
+        Language::Japanese => (
+            format!("{} :参戦ID\n参加者募集！\n{}\nhttps://t.co/x", raid_id, boss_name),
+            "ja",
+        ),
+    };
+
+    let user = json!({
+        "id": 1000 + tweet_id,
+        "id_str": (1000 + tweet_id).to_string(),
+        "name": "Test User",
+        "screen_name": format!("user{}", tweet_id),
+        "location": null,
+        "url": null,
+        "description": null,
+        "protected": false,
+        "verified": false,
+        "followers_count": 0,
+        "friends_count": 0,
+        "listed_count": 0,
+        "favourites_count": 0,
+        "statuses_count": 1,
+        "created_at": "Mon Jan 01 00:00:00 +0000 2017",
+        "utc_offset": null,
+        "time_zone": null,
+        "geo_enabled": false,
+        "lang": lang,
+        "contributors_enabled": false,
+        "is_translator": false,
+        "profile_background_color": "000000",
+        "profile_background_image_url": "http://abs.twimg.com/images/themes/theme1/bg.png",
+        "profile_background_image_url_https": "https://abs.twimg.com/images/themes/theme1/bg.png",
+        "profile_background_tile": false,
+        "profile_link_color": "000000",
+        "profile_sidebar_border_color": "000000",
+        "profile_sidebar_fill_color": "000000",
+        "profile_text_color": "000000",
+        "profile_use_background_image": true,
+        "profile_image_url": "http://pbs.twimg.com/profile_images/1/normal.png",
+        "profile_image_url_https": "https://pbs.twimg.com/profile_images/1/normal.png",
+        "default_profile": true,
+        "default_profile_image": false,
+        "following": null,
+        "follow_request_sent": null,
+        "notifications": null,
+    });
+
+    let mut tweet = json!({
+        "created_at": "Wed Oct 18 12:00:00 +0000 2017",
+        "id": tweet_id,
+        "id_str": tweet_id.to_string(),
+        "text": text,
+        "source": GAME_SOURCE,
+        "truncated": false,
+        "in_reply_to_status_id": null,
+        "in_reply_to_status_id_str": null,
+        "in_reply_to_user_id": null,
+        "in_reply_to_user_id_str": null,
+        "in_reply_to_screen_name": null,
+        "user": user,
+        "geo": null,
+        "coordinates": null,
+        "place": null,
+        "contributors": null,
+        "is_quote_status": false,
+        "retweet_count": 0,
+        "favorite_count": 0,
+        "entities": {
+            "hashtags": [],
+            "urls": [],
+            "user_mentions": [],
+            "symbols": [],
+        },
+        "favorited": false,
+        "retweeted": false,
+        "filter_level": "low",
+        "lang": lang,
+        "timestamp_ms": "1508328000000",
+    });
+
+    if let Some(media) = media {
+        tweet["entities"]["media"] = media.clone();
+        tweet["extended_entities"] = json!({ "media": media });
+    }
+
+    tweet
+}
+
+// Starts the server on an ephemeral port, streaming tweets from `twitter`
+// unless the config has its own stream URL
+pub fn start_server(twitter: &MockTwitter, mut config: Config) -> SocketAddr {
+    if config.twitter_stream_url.is_none() {
+        config.twitter_stream_url = Some(twitter.url());
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let token = Token::new(
+        "consumer_key".to_string(),
+        "consumer_secret".to_string(),
+        "access_token".to_string(),
+        "access_token_secret".to_string(),
+    );
+
+    thread::spawn(move || petronel_gbfrf::run(token, config, listener).unwrap());
+
+    addr
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub opcode: u8,
+    // Length of the frame header, which depends on the payload length
+    pub header_len: usize,
+    pub payload: Vec<u8>,
+}
+
+pub struct WebsocketClient {
+    stream: TcpStream,
+}
+
+impl WebsocketClient {
+    pub fn connect(addr: SocketAddr) -> Self {
+        Self::connect_with_headers(addr, &[])
+    }
+
+    pub fn connect_with_headers(addr: SocketAddr, headers: &[(&str, &str)]) -> Self {
+        let extra_headers = headers
+            .iter()
+            .map(|&(name, value)| format!("{}: {}\r\n", name, value))
+            .collect::<String>();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECONDS)))
+            .unwrap();
+
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: binary\r\n\
+             {}\r\n",
+            addr,
+            extra_headers
+        ).unwrap();
+
+        // Read the response headers one byte at a time, to avoid consuming
+        // any websocket frames that follow
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 101"),
+            "unexpected handshake response: {}",
+            response
+        );
+
+        WebsocketClient { stream }
+    }
+
+    pub fn send(&mut self, data: protobuf::request_message::Data) {
+        self.send_message(RequestMessage {
+            data: Some(data),
+            ..Default::default()
+        });
+    }
+
+    pub fn send_message(&mut self, message: RequestMessage) {
+        let mut payload = Vec::new();
+        message.encode(&mut payload).unwrap();
+        self.send_frame(0x2, &payload);
+    }
+
+    // Client frames must be masked
+    pub fn send_frame(&mut self, opcode: u8, payload: &[u8]) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x80 | opcode];
+
+        match payload.len() {
+            len @ 0...125 => frame.push(0x80 | len as u8),
+            len @ 126...65535 => {
+                frame.push(0x80 | 126);
+                frame.extend(&[(len >> 8) as u8, len as u8]);
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend((0..8).rev().map(|i| (len >> (i * 8)) as u8));
+            }
+        }
+
+        frame.extend(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+
+        self.send_raw(&frame);
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    pub fn recv_frame(&mut self) -> Frame {
+        let mut header = [0; 2];
+        self.stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[1] & 0x80, 0, "server frames must not be masked");
+
+        let (len, header_len) = match header[1] & 0x7F {
+            126 => {
+                let mut ext = [0; 2];
+                self.stream.read_exact(&mut ext).unwrap();
+                ((ext[0] as usize) << 8 | ext[1] as usize, 4)
+            }
+            127 => {
+                let mut ext = [0; 8];
+                self.stream.read_exact(&mut ext).unwrap();
+                (ext.iter().fold(0, |acc, &b| acc << 8 | b as usize), 10)
+            }
+            len => (len as usize, 2),
+        };
+
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload).unwrap();
+
+        Frame {
+            opcode: header[0] & 0x0F,
+            header_len,
+            payload,
+        }
+    }
+
+    pub fn recv_message(&mut self) -> ResponseMessage {
+        let frame = self.recv_frame();
+        assert_eq!(frame.opcode, 0x2, "expected a binary frame, got {:?}", frame);
+        ResponseMessage::decode(frame.payload).unwrap()
+    }
+
+    pub fn recv_data(&mut self) -> Response {
+        self.recv_message().data.expect("response has no data")
+    }
+
+    // Skips messages until one matches the predicate
+    pub fn recv_message_matching<F>(&mut self, predicate: F) -> ResponseMessage
+    where
+        F: Fn(&ResponseMessage) -> bool,
+    {
+        loop {
+            let frame = self.recv_frame();
+            if frame.opcode != 0x2 {
+                continue;
+            }
+
+            let message = ResponseMessage::decode(frame.payload).unwrap();
+            if predicate(&message) {
+                return message;
+            }
+        }
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    // All values of a header, in the order they were sent
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+            .collect()
+    }
+}
+
+// Sends a single HTTP/1.1 request on a new connection
+pub fn http_request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> HttpResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECONDS)))
+        .unwrap();
+
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, addr);
+    for &(name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("Content-Length: 0\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+    let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+
+    let mut response_headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap().trim().to_string();
+        let value = parts.next().unwrap_or("").trim().to_string();
+        response_headers.push((name, value));
+    }
+
+    let mut response = HttpResponse {
+        status,
+        headers: response_headers,
+        body: vec![],
+    };
+
+    let content_length = response
+        .header_values("Content-Length")
+        .first()
+        .map_or(0, |len| len.parse().unwrap());
+
+    if method != "HEAD" {
+        response.body = vec![0; content_length];
+        reader.read_exact(&mut response.body).unwrap();
+    }
+
+    response
+}
@@ -0,0 +1,93 @@
+extern crate petronel;
+extern crate petronel_gbfrf;
+extern crate prost;
+#[macro_use]
+extern crate serde_json;
+
+mod support;
+
+use petronel_gbfrf::{Config, ProxyConfig, ProxyMode};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+use support::{MockTwitter, WebsocketClient};
+
+// One connection per client IP, so tests can tell which address was used
+fn config(mode: ProxyMode, trusted_proxy: &str) -> Config {
+    let mut config = Config::default();
+    config.proxy = ProxyConfig {
+        mode,
+        trusted_proxies: vec![trusted_proxy.parse::<IpAddr>().unwrap()],
+    };
+    config.limits.max_connections_per_ip = 1;
+    config
+}
+
+#[test]
+fn forwarded_headers_from_untrusted_peers_are_ignored() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, config(ProxyMode::Headers, "10.0.0.1"));
+
+    let mut first = WebsocketClient::connect_with_headers(
+        server,
+        &[("X-Forwarded-For", "203.0.113.1")],
+    );
+    assert_eq!(first.recv_frame().opcode, 0x9);
+
+    // We connect from 127.0.0.1, which isn't a trusted proxy, so the header
+    // can't be used to dodge the per-IP connection limit
+    let mut second = WebsocketClient::connect_with_headers(
+        server,
+        &[("X-Forwarded-For", "203.0.113.2")],
+    );
+    let close = second.recv_frame();
+    assert_eq!(close.opcode, 0x8);
+    assert_eq!(&close.payload[..2], &[0x03, 0xF0]); // 1008
+}
+
+#[test]
+fn forwarded_headers_from_trusted_proxies_are_used() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, config(ProxyMode::Headers, "127.0.0.1"));
+
+    let mut first = WebsocketClient::connect_with_headers(
+        server,
+        &[("X-Forwarded-For", "203.0.113.1")],
+    );
+    assert_eq!(first.recv_frame().opcode, 0x9);
+
+    let mut second = WebsocketClient::connect_with_headers(
+        server,
+        &[("X-Forwarded-For", "203.0.113.2")],
+    );
+    assert_eq!(second.recv_frame().opcode, 0x9);
+
+    let mut same_client = WebsocketClient::connect_with_headers(
+        server,
+        &[("X-Forwarded-For", "203.0.113.1")],
+    );
+    assert_eq!(same_client.recv_frame().opcode, 0x8);
+}
+
+#[test]
+fn malformed_proxy_headers_are_dropped() {
+    let twitter = MockTwitter::start();
+    let server = support::start_server(&twitter, config(ProxyMode::ProxyProtocol, "127.0.0.1"));
+
+    let mut stream = TcpStream::connect(server).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+        .write_all(
+            b"PROXY TCP4 not-an-address 10.0.0.1 56324 80\r\n\
+              GET /api/bosses.json HTTP/1.1\r\n\
+              Host: localhost\r\n\r\n",
+        )
+        .unwrap();
+
+    // Closed without a response. It may be reset, since the request is unread.
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        Ok(_) => assert!(response.is_empty()),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+    }
+}